@@ -0,0 +1,9 @@
+-- Your SQL goes here
+create table webhook_dead_letters (
+	id SERIAL PRIMARY KEY,
+	ts TIMESTAMP NOT NULL,
+	url VARCHAR NOT NULL,
+	payload TEXT NOT NULL,
+	attempts INTEGER NOT NULL,
+	last_error TEXT NOT NULL
+)
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+create table adjustments (
+	id SERIAL PRIMARY KEY,
+	ts TIMESTAMP NOT NULL,
+	admin_id VARCHAR NOT NULL,
+	user_id VARCHAR NOT NULL,
+	deng_id INTEGER REFERENCES dengs (id),
+	points INTEGER NOT NULL,
+	reason VARCHAR NOT NULL,
+	undone BOOLEAN NOT NULL DEFAULT false
+)
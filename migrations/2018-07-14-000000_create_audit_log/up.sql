@@ -0,0 +1,10 @@
+-- Your SQL goes here
+create table audit_log (
+	id SERIAL PRIMARY KEY,
+	ts TIMESTAMP NOT NULL,
+	actor VARCHAR NOT NULL,
+	action VARCHAR NOT NULL,
+	before TEXT,
+	after TEXT,
+	source VARCHAR NOT NULL
+)
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+drop table deng_points;
+
+alter table dengs drop column points;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+alter table dengs add column points INTEGER NOT NULL DEFAULT 0;
+
+create table deng_points (
+	id SERIAL PRIMARY KEY,
+	deng_id INTEGER NOT NULL REFERENCES dengs (id),
+	rule VARCHAR NOT NULL,
+	points INTEGER NOT NULL
+);
+
+-- Score existing dengs with the rules that were hard-coded before
+insert into deng_points (deng_id, rule, points)
+	select id, 'users_first_deng', 1 from dengs where successful and users_first_deng;
+
+insert into deng_points (deng_id, rule, points)
+	select id, 'days_first_deng', 1 from dengs where successful and days_first_deng;
+
+update dengs set points = coalesce((select sum(deng_points.points) from deng_points where deng_points.deng_id = dengs.id), 0);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table dengs add column thread_reply BOOLEAN NOT NULL DEFAULT false;
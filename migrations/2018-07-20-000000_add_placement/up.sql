@@ -0,0 +1,16 @@
+-- Your SQL goes here
+alter table dengs add column placement INTEGER;
+
+-- Rank each user's first successful deng of the day by when it happened.
+-- Days start at 9am Perth time, which is 1am UTC
+update dengs set placement = ranked.placement
+from (
+	select id, row_number() over (partition by date(ts - interval '1 hour') order by ts) as placement
+	from (
+		select distinct on (user_id, date(ts - interval '1 hour')) id, ts
+		from dengs
+		where successful
+		order by user_id, date(ts - interval '1 hour'), ts
+	) as firsts
+) as ranked
+where dengs.id = ranked.id;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+drop table season_standings;
+
+drop table seasons;
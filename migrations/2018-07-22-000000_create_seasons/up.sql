@@ -0,0 +1,19 @@
+-- Your SQL goes here
+create table seasons (
+	id SERIAL PRIMARY KEY,
+	name VARCHAR NOT NULL,
+	started TIMESTAMP NOT NULL,
+	ended TIMESTAMP
+);
+
+create table season_standings (
+	id SERIAL PRIMARY KEY,
+	season_id INTEGER NOT NULL REFERENCES seasons (id),
+	user_id VARCHAR NOT NULL,
+	points INTEGER NOT NULL,
+	rank INTEGER NOT NULL
+);
+
+-- Everything so far is the first season
+insert into seasons (name, started)
+	select 'Season 1', coalesce(min(ts), now() at time zone 'utc') from dengs;
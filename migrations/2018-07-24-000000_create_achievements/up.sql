@@ -0,0 +1,9 @@
+-- Your SQL goes here
+create table achievements (
+	id SERIAL PRIMARY KEY,
+	user_id VARCHAR NOT NULL,
+	achievement VARCHAR NOT NULL,
+	deng_id INTEGER NOT NULL REFERENCES dengs (id),
+	unlocked TIMESTAMP NOT NULL,
+	UNIQUE (user_id, achievement)
+);
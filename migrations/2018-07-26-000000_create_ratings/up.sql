@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table ratings (
+	id SERIAL PRIMARY KEY,
+	user_id VARCHAR NOT NULL,
+	day DATE NOT NULL,
+	rating DOUBLE PRECISION NOT NULL,
+	change DOUBLE PRECISION NOT NULL,
+	placement INTEGER NOT NULL,
+	racers INTEGER NOT NULL
+);
+
+create index ratings_day on ratings (day);
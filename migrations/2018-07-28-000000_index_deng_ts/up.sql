@@ -0,0 +1,2 @@
+-- Your SQL goes here
+create index dengs_ts on dengs (ts);
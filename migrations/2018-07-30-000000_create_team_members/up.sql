@@ -0,0 +1,8 @@
+-- Your SQL goes here
+create table team_members (
+	id SERIAL PRIMARY KEY,
+	team VARCHAR NOT NULL,
+	user_id VARCHAR NOT NULL,
+	joined TIMESTAMP NOT NULL,
+	"left" TIMESTAMP
+);
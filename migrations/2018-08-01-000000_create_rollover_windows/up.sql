@@ -0,0 +1,8 @@
+-- Your SQL goes here
+create table rollover_windows (
+	id SERIAL PRIMARY KEY,
+	day DATE NOT NULL UNIQUE,
+	window_start TIMESTAMP NOT NULL,
+	window_end TIMESTAMP NOT NULL,
+	rolled_over TIMESTAMP NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+alter table dengs drop constraint dengs_message;
+alter table dengs drop column slack_ts;
+alter table dengs drop column channel_id;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+alter table dengs add column channel_id VARCHAR;
+alter table dengs add column slack_ts VARCHAR;
+alter table dengs add constraint dengs_message unique (channel_id, slack_ts);
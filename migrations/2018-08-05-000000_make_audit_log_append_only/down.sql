@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+drop trigger audit_log_no_truncate on audit_log;
+drop trigger audit_log_no_changes on audit_log;
+drop function audit_log_append_only();
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create function audit_log_append_only() returns trigger as $$
+begin
+	raise exception 'audit_log is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_log_no_changes before update or delete on audit_log
+	for each row execute procedure audit_log_append_only();
+
+create trigger audit_log_no_truncate before truncate on audit_log
+	for each statement execute procedure audit_log_append_only();
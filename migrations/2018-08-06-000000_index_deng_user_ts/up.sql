@@ -0,0 +1,2 @@
+-- Your SQL goes here
+create index dengs_user_ts on dengs (user_id, ts);
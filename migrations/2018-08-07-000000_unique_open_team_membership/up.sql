@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Each user can only be on one team at a time
+create unique index team_members_open_user on team_members (user_id) where "left" is null;
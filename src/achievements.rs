@@ -0,0 +1,162 @@
+use chrono::{self, DateTime, Datelike, FixedOffset, NaiveDate};
+use diesel::PgConnection;
+use runner::DayCycle;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use storage::{self, NewAchievement, Source};
+use streaks::SkipDays;
+use types::{Deng, Error, UnlockedAchievement};
+
+// How long someone has to have been away for their return to count as a comeback
+const COMEBACK_DAYS: i64 = 14;
+
+// Everything an achievement knows about a newly stored successful deng
+pub struct AchievementContext<'a> {
+    pub deng: &'a Deng,
+    // The user's earlier successful dengs that haven't been voided, newest first, not including this one
+    pub history: &'a [Deng],
+    pub rolled_over: DateTime<FixedOffset>,
+    pub skip: SkipDays,
+}
+
+pub trait Achievement: Send {
+    // Stored against each unlock, so must never change once released
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    fn badge(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+
+    fn unlocked(&self, context: &AchievementContext) -> bool;
+}
+
+struct FirstDeng;
+
+impl Achievement for FirstDeng {
+    fn id(&self) -> &'static str { "first_deng" }
+    fn name(&self) -> &'static str { "First Deng" }
+    fn badge(&self) -> &'static str { ":hatching_chick:" }
+    fn description(&self) -> &'static str { "Denged for the very first time" }
+
+    fn unlocked(&self, context: &AchievementContext) -> bool {
+        !context.history.iter().any(|deng| deng.successful)
+    }
+}
+
+struct EarlyBird;
+
+impl Achievement for EarlyBird {
+    fn id(&self) -> &'static str { "early_bird" }
+    fn name(&self) -> &'static str { "Early Bird" }
+    fn badge(&self) -> &'static str { ":bird:" }
+    fn description(&self) -> &'static str { "Denged within a minute of the new day starting" }
+
+    fn unlocked(&self, context: &AchievementContext) -> bool {
+        let since_rollover = DayCycle::local(context.deng.ts).signed_duration_since(context.rolled_over);
+        since_rollover >= chrono::Duration::zero() && since_rollover < chrono::Duration::minutes(1)
+    }
+}
+
+struct Comeback;
+
+impl Achievement for Comeback {
+    fn id(&self) -> &'static str { "comeback" }
+    fn name(&self) -> &'static str { "Comeback" }
+    fn badge(&self) -> &'static str { ":boomerang:" }
+    fn description(&self) -> &'static str { "Denged again after two weeks away" }
+
+    fn unlocked(&self, context: &AchievementContext) -> bool {
+        let away = Duration::from_secs(COMEBACK_DAYS as u64 * 24 * 60 * 60);
+        match context.history.iter().find(|deng| deng.successful) {
+            Some(last) => context.deng.ts.duration_since(last.ts).map(|gap| gap >= away).unwrap_or(false),
+            None => false
+        }
+    }
+}
+
+struct PerfectMonth;
+
+impl Achievement for PerfectMonth {
+    fn id(&self) -> &'static str { "perfect_month" }
+    fn name(&self) -> &'static str { "Perfect Month" }
+    fn badge(&self) -> &'static str { ":calendar:" }
+    fn description(&self) -> &'static str { "Denged on every day of a calendar month" }
+
+    // Checks the most recent month to have finished, counting today if it's the last day of the month.
+    // Days that don't break a streak don't need a deng either
+    fn unlocked(&self, context: &AchievementContext) -> bool {
+        let today = DayCycle::day_of(context.deng.ts);
+        let month_start = NaiveDate::from_ymd(today.year(), today.month(), 1);
+        let (first, last) = if today.succ().month() != today.month() {
+            (month_start, today)
+        } else {
+            let last = month_start.pred();
+            (NaiveDate::from_ymd(last.year(), last.month(), 1), last)
+        };
+
+        let days = context.history.iter()
+            .filter(|deng| deng.successful)
+            .map(|deng| DayCycle::day_of(deng.ts))
+            .chain(Some(today))
+            .collect::<BTreeSet<_>>();
+
+        let mut day = first;
+        while day <= last {
+            if !days.contains(&day) && !context.skip.skips(day) {
+                return false;
+            }
+            day = day.succ();
+        }
+        true
+    }
+}
+
+pub fn all() -> Vec<Box<Achievement>> {
+    vec![
+        Box::new(FirstDeng),
+        Box::new(EarlyBird),
+        Box::new(Comeback),
+        Box::new(PerfectMonth),
+    ]
+}
+
+// Stores any achievements the deng has unlocked, returning an announcement for each
+pub fn unlock(conn: &PgConnection, context: &AchievementContext) -> Result<Vec<String>, Error> {
+    let user_id = &context.deng.user_id;
+    let already = storage::load_user_achievements(conn, user_id)?;
+
+    let mut announcements = vec![];
+    for achievement in all() {
+        if already.iter().any(|unlocked| unlocked.achievement == achievement.id()) || !achievement.unlocked(context) {
+            continue;
+        }
+
+        storage::store_achievement(conn, &NewAchievement::new(user_id, achievement.id(), context.deng), Source::Rtm)?;
+        info!("{} unlocked achievement {}", user_id, achievement.id());
+
+        announcements.push(format!("<@{}> unlocked {} *{}* - {}",
+                                   user_id, achievement.badge(), achievement.name(), achievement.description()));
+    }
+
+    Ok(announcements)
+}
+
+// Each user's badges, in the order the achievements are defined
+pub fn badges_by_user(unlocked: &[UnlockedAchievement]) -> HashMap<String, String> {
+    let definitions = all();
+
+    unlocked.iter()
+        .fold(HashMap::new(), |mut map: HashMap<&str, Vec<usize>>, unlock| {
+            match definitions.iter().position(|achievement| achievement.id() == unlock.achievement) {
+                Some(index) => map.entry(unlock.user_id.as_str()).or_insert_with(Vec::new).push(index),
+                None => warn!("Unknown achievement '{}' for {}", unlock.achievement, unlock.user_id)
+            }
+            map
+        })
+        .into_iter()
+        .map(|(user_id, mut indices)| {
+            indices.sort();
+            let badges = indices.into_iter().map(|index| definitions[index].badge()).collect::<Vec<_>>().join("");
+            (String::from(user_id), badges)
+        })
+        .collect()
+}
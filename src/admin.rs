@@ -0,0 +1,150 @@
+use config::{Config, SharedConfig};
+use seasons;
+use ratings;
+use teams;
+use diesel::PgConnection;
+use runner::DayCycle;
+use storage::{self, NewAdjustment, Source};
+use types::{Adjustment, AuditEntry, Error};
+
+// Keep audit log listings within what Slack will happily display
+const MAX_AUDIT_VALUE_LENGTH: usize = 200;
+
+pub enum Outcome {
+    // The change was made, and should be announced with this message
+    Announce(String),
+    // Nothing was changed, and the admin should be told why
+    Reject(String),
+}
+
+pub fn void(conn: &PgConnection, admin_id: &str, deng_id: i32, reason: String, source: Source) -> Result<Outcome, Error> {
+    let deng = match storage::find(conn, deng_id)? {
+        Some(ref deng) if !deng.successful => {
+            return Ok(Outcome::Reject(format!("Deng {} was not a successful deng, so it has no points to void.", deng_id)))
+        },
+        Some(deng) => deng,
+        None => return Ok(Outcome::Reject(format!("There is no deng with ID {}.", deng_id)))
+    };
+
+    if storage::find_void(conn, deng_id)?.is_some() {
+        return Ok(Outcome::Reject(format!("Deng {} has already been voided.", deng_id)));
+    }
+
+    let adjustment = storage::store_adjustment(conn, &NewAdjustment::new_void(String::from(admin_id), &deng, reason), source)?;
+
+    info!("{} voided deng {}", admin_id, deng_id);
+
+    Ok(Outcome::Announce(format!("<@{}> voided <@{}>'s deng from {} (-{}){}",
+                                 admin_id, deng.user_id, DayCycle::local(deng.ts).format("%e %b %H:%M:%S"),
+                                 deng.value(), reason_suffix(&adjustment))))
+}
+
+pub fn adjust(conn: &PgConnection,
+              admin_id: &str,
+              user_id: String,
+              points: i32,
+              reason: String,
+              source: Source) -> Result<Outcome, Error> {
+
+    let adjustment = NewAdjustment::new_points(String::from(admin_id), user_id, points, reason);
+    let adjustment = storage::store_adjustment(conn, &adjustment, source)?;
+
+    info!("{} adjusted {}'s score by {}", admin_id, adjustment.user_id, points);
+
+    Ok(Outcome::Announce(format!("<@{}> {}", admin_id, describe(&adjustment))))
+}
+
+pub fn undo(conn: &PgConnection, admin_id: &str, source: Source) -> Result<Outcome, Error> {
+    let adjustment = match storage::last_adjustment(conn, admin_id)? {
+        Some(adjustment) => storage::undo_adjustment(conn, admin_id, &adjustment, source)?,
+        None => return Ok(Outcome::Reject(String::from("You have no adjustments left to undo.")))
+    };
+
+    info!("{} undid adjustment {}", admin_id, adjustment.id);
+
+    Ok(Outcome::Announce(format!("<@{}> undid their last adjustment, where they {}", admin_id, describe(&adjustment))))
+}
+
+pub fn reload_config(conn: &PgConnection, admin_id: &str, config: &SharedConfig, source: Source) -> Result<Outcome, Error> {
+    let reloaded = Config::reload();
+    let previous = config.replace(reloaded.clone());
+
+    storage::audit_config_reload(conn, admin_id, &previous, &reloaded, source)?;
+
+    if let Err(e) = teams::sync(conn, &reloaded, source) {
+        error!("Could not update teams after reloading the configuration: {}", e);
+    }
+
+    info!("{} reloaded the configuration", admin_id);
+
+    Ok(Outcome::Announce(format!("<@{}> reloaded the dengbot configuration", admin_id)))
+}
+
+pub fn end_season(conn: &PgConnection, admin_id: &str, name: Option<String>, config: &Config, source: Source) -> Result<Outcome, Error> {
+    let season = match storage::current_season(conn)? {
+        Some(season) => season,
+        None => return Ok(Outcome::Reject(String::from("There is no season in progress.")))
+    };
+
+    info!("{} ended season {}", admin_id, season.name);
+
+    seasons::end(conn, admin_id, &season, name, config, source).map(Outcome::Announce)
+}
+
+pub fn recompute_ratings(conn: &PgConnection, admin_id: &str, config: &Config, source: Source) -> Result<Outcome, Error> {
+    if !config.ratings_enabled {
+        return Ok(Outcome::Reject(String::from("Ratings are not enabled.")));
+    }
+
+    let count = ratings::recompute(conn, admin_id, config, source)?;
+
+    info!("{} recomputed {} ratings", admin_id, count);
+
+    Ok(Outcome::Announce(format!("<@{}> recalculated everyone's ratings from scratch", admin_id)))
+}
+
+pub fn format_audit_log(entries: &[AuditEntry]) -> String {
+    if entries.is_empty() {
+        return String::from("The audit log is empty.");
+    }
+
+    entries.iter()
+        .map(|entry| {
+            let mut line = format!("`#{}` {} *{}* by <@{}> via {}",
+                                   entry.id, DayCycle::local(entry.ts).format("%e %b %H:%M:%S"),
+                                   entry.action, entry.actor, entry.source);
+            if let Some(ref before) = entry.before {
+                line.push_str(&format!("\n\tbefore: `{}`", truncate(before)));
+            }
+            if let Some(ref after) = entry.after {
+                line.push_str(&format!("\n\tafter: `{}`", truncate(after)));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn truncate(value: &str) -> String {
+    match value.char_indices().nth(MAX_AUDIT_VALUE_LENGTH) {
+        Some((index, _)) => format!("{}...", &value[..index]),
+        None => String::from(value)
+    }
+}
+
+fn describe(adjustment: &Adjustment) -> String {
+    match adjustment.deng_id {
+        Some(deng_id) => format!("voided <@{}>'s deng {}{}", adjustment.user_id, deng_id, reason_suffix(adjustment)),
+        None if adjustment.points < 0 => {
+            format!("deducted {} points from <@{}>{}", -adjustment.points, adjustment.user_id, reason_suffix(adjustment))
+        },
+        None => format!("granted <@{}> {} points{}", adjustment.user_id, adjustment.points, reason_suffix(adjustment))
+    }
+}
+
+fn reason_suffix(adjustment: &Adjustment) -> String {
+    match adjustment.reason.len() {
+        0 => String::new(),
+        _ => format!(": {}", adjustment.reason)
+    }
+}
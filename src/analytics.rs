@@ -0,0 +1,76 @@
+use chrono::{Datelike, Timelike};
+use runner::DayCycle;
+use types::Deng;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+// How many minutes after 9am to break down first-of-day dengs by, which covers the 15 minutes of fuzz on the rollover
+const ROLLOVER_MINUTES: usize = 20;
+
+// Successful dengs bucketed by local weekday and hour
+#[derive(Debug, Serialize)]
+pub struct Heatmap {
+    // Monday first, then midnight first
+    pub counts: Vec<Vec<u32>>,
+    // First-of-day dengs by the minute after 9am they landed in. If the fuzz is working these should be spread out
+    pub rollover_minutes: Vec<u32>,
+    pub total: u32,
+}
+
+impl Heatmap {
+    pub fn from_dengs<'a, I>(dengs: I) -> Self where I: IntoIterator<Item=&'a Deng> {
+        let mut heatmap = Heatmap {
+            counts: vec![vec![0; 24]; 7],
+            rollover_minutes: vec![0; ROLLOVER_MINUTES],
+            total: 0,
+        };
+
+        for deng in dengs.into_iter().filter(|deng| deng.successful) {
+            let local = DayCycle::local(deng.ts);
+            heatmap.counts[local.weekday().num_days_from_monday() as usize][local.hour() as usize] += 1;
+            heatmap.total += 1;
+
+            if deng.days_first_deng && local.hour() == 9 && (local.minute() as usize) < ROLLOVER_MINUTES {
+                heatmap.rollover_minutes[local.minute() as usize] += 1;
+            }
+        }
+
+        heatmap
+    }
+
+    fn shade(count: u32, max: u32) -> char {
+        match (count, max) {
+            (0, _) | (_, 0) => SHADES[0],
+            _ => SHADES[1 + (count - 1) as usize * (SHADES.len() - 1) / max as usize]
+        }
+    }
+
+    // A grid of weekdays against hours, meant to go in a Slack code block
+    pub fn format(&self, title: &str) -> String {
+        if self.total == 0 {
+            return format!("*{}*\nNo dengs yet!", title);
+        }
+
+        let max = self.counts.iter().flat_map(|hours| hours.iter().cloned()).max().unwrap_or(0);
+
+        let header = (0..24)
+            .map(|hour| if hour % 3 == 0 { format!("{:<3}", hour) } else { String::new() })
+            .collect::<String>();
+
+        let rows = self.counts.iter()
+            .zip(WEEKDAYS.iter())
+            .map(|(hours, weekday)| {
+                let cells = hours.iter().map(|&count| Self::shade(count, max)).collect::<String>();
+                format!("{} |{}|", weekday, cells)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let rollover = self.rollover_minutes.iter()
+            .map(|&count| Self::shade(count, self.rollover_minutes.iter().cloned().max().unwrap_or(0)))
+            .collect::<String>();
+
+        format!("*{}* ({} dengs, busiest hour has {})\n```\n     {}\n{}\n\n9:00 |{}| 9:{}\n```",
+                title, self.total, max, header, rows, rollover, ROLLOVER_MINUTES)
+    }
+}
@@ -0,0 +1,47 @@
+use reqwest;
+use serde_json::{self, Value};
+use slack;
+use std::io::Cursor;
+use types::Error;
+
+// Posts a message to a channel as the bot user
+pub fn post(channel_id: &str, text: &str) -> Result<(), Error> {
+    let client = slack::api::requests::default_client()
+        .map_err(|e| Error::from(format!("Could not create Slack web client: {}", e)))?;
+
+    let request = slack::api::chat::PostMessageRequest {
+        channel: channel_id,
+        text,
+        as_user: Some(true),
+        ..Default::default()
+    };
+
+    slack::api::chat::post_message(&client, dotenv!("SLACK_API_KEY"), &request)
+        .map(|_| ())
+        .map_err(|e| Error::from(format!("Could not post message to Slack: {}", e)))
+}
+
+// Uploads a file and shares it in a channel. The Slack client doesn't support multipart uploads, so this goes direct
+pub fn upload(api_url: &str, channel_id: &str, file_name: &str, contents: Vec<u8>, comment: &str) -> Result<(), Error> {
+    let url = format!("{}/files.upload", api_url.trim_right_matches('/'));
+    let file = reqwest::multipart::Part::reader(Cursor::new(contents))
+        .file_name(String::from(file_name));
+
+    let form = reqwest::multipart::Form::new()
+        .text("token", dotenv!("SLACK_API_KEY"))
+        .text("channels", String::from(channel_id))
+        .text("filename", String::from(file_name))
+        .text("initial_comment", String::from(comment))
+        .part("file", file);
+
+    let response: Value = reqwest::Client::new()
+        .post(&url)
+        .multipart(form)
+        .send()?
+        .json()?;
+
+    match response.get("ok").and_then(Value::as_bool) {
+        Some(true) => Ok(()),
+        _ => Err(Error::from(format!("Could not upload file to Slack: {}", serde_json::to_string(&response)?)))
+    }
+}
@@ -0,0 +1,83 @@
+use chrono;
+use config::Config;
+use diesel::PgConnection;
+use runner::DayCycle;
+use std::time::SystemTime;
+use storage::{self, NewAdjustment, NewRolloverWindow, Source};
+use types::{Deng, Error};
+
+// Anyone who makes this many attempts around the rollover is probably spamming to beat the fuzz
+pub struct Thresholds {
+    max_attempts: i64,
+    window_minutes: i64,
+    disqualify: bool,
+}
+
+impl Thresholds {
+    pub fn from_config(config: &Config) -> Self {
+        Thresholds {
+            max_attempts: config.anticheat_max_attempts,
+            window_minutes: config.anticheat_window_minutes,
+            disqualify: config.anticheat_disqualify,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 0
+    }
+}
+
+// From a few minutes before 9am until a few minutes after the day actually rolled over, fuzz and all
+pub fn record_window(conn: &PgConnection, day_cycle: &DayCycle, config: &Config) -> Result<NewRolloverWindow, Error> {
+    let margin = chrono::Duration::minutes(Thresholds::from_config(config).window_minutes);
+
+    let window = NewRolloverWindow {
+        day: DayCycle::day_of(SystemTime::from(day_cycle.rolled_over())),
+        window_start: SystemTime::from(day_cycle.start() - margin),
+        window_end: SystemTime::from(day_cycle.rolled_over() + margin),
+        rolled_over: SystemTime::from(day_cycle.rolled_over()),
+    };
+
+    storage::store_rollover_window(conn, &window)?;
+    Ok(window)
+}
+
+pub struct Flag {
+    // For the meta channel
+    pub message: String,
+    // Whether the deng was voided for it
+    pub voided: bool,
+}
+
+// Checks a user's first deng of the day, or a failed attempt, for a burst of attempts around the rollover
+pub fn check(conn: &PgConnection, config: &Config, window: &NewRolloverWindow, deng: &Deng) -> Result<Option<Flag>, Error> {
+    let thresholds = Thresholds::from_config(config);
+    if !thresholds.enabled() || deng.ts < window.window_start || deng.ts > window.window_end {
+        return Ok(None);
+    }
+    if deng.successful && !deng.users_first_deng {
+        return Ok(None);
+    }
+
+    let attempts = storage::count_attempts(conn, &deng.user_id, window.window_start, deng.ts)?;
+    // Failures are only flagged as the burst reaches the threshold, so each one after it doesn't flag again
+    let flagged = if deng.successful { attempts >= thresholds.max_attempts } else { attempts == thresholds.max_attempts };
+    if !flagged {
+        return Ok(None);
+    }
+
+    warn!("{} made {} attempts around the rollover", deng.user_id, attempts);
+    storage::audit_rollover_flag(conn, deng, attempts, Source::Rtm)?;
+
+    let mut message = format!(":rotating_light: <@{}> made {} attempts in the {} minutes around the rollover",
+                              deng.user_id, attempts, thresholds.window_minutes);
+
+    let voided = thresholds.disqualify && deng.successful;
+    if voided {
+        let reason = format!("{} attempts around the rollover", attempts);
+        storage::store_adjustment(conn, &NewAdjustment::new_void(String::from("dengbot"), deng, reason), Source::Rtm)?;
+        message.push_str(&format!(", so their deng has been voided (-{})", deng.value()));
+    }
+
+    Ok(Some(Flag { message, voided }))
+}
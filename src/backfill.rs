@@ -0,0 +1,181 @@
+extern crate zip;
+
+use config::Config;
+use diesel::PgConnection;
+use replay::{Attempt, Replay, Replayed};
+use runner::Rollovers;
+use scoreboard::{self, Penalties};
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use storage::{self, Source};
+use types::{self, Adjustment, Error, MessageId};
+
+#[derive(Deserialize)]
+struct ExportChannel {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ExportUser {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ExportMessage {
+    user: Option<String>,
+    text: Option<String>,
+    ts: Option<String>,
+    thread_ts: Option<String>,
+    subtype: Option<String>,
+}
+
+// The parts of a Slack export we need: the channel and user lists, and a file of messages
+// for each day the listen channel was active
+struct Export {
+    files: HashMap<String, String>,
+}
+
+impl Export {
+    // Reads an export either still zipped or extracted to a directory
+    fn read(path: &Path, channel_name: &str) -> Result<Self, Error> {
+        let day_prefix = format!("{}/", channel_name);
+        let wanted = |name: &str| {
+            name == "channels.json" || name == "users.json" || (name.starts_with(&day_prefix) && name.ends_with(".json"))
+        };
+
+        let mut files = HashMap::new();
+
+        if path.is_dir() {
+            for name in &["channels.json", "users.json"] {
+                files.insert(String::from(*name), read_file(&path.join(name))?);
+            }
+            for entry in fs::read_dir(path.join(channel_name))? {
+                let entry = entry?;
+                let name = format!("{}{}", day_prefix, entry.file_name().to_string_lossy());
+                if wanted(&name) {
+                    files.insert(name, read_file(&entry.path())?);
+                }
+            }
+        } else {
+            let mut archive = zip::ZipArchive::new(File::open(path)?)
+                .map_err(|e| Error::from(format!("Could not open export: {}", e)))?;
+            for index in 0..archive.len() {
+                let mut file = archive.by_index(index)
+                    .map_err(|e| Error::from(format!("Could not read export: {}", e)))?;
+                let name = String::from(file.name());
+                if wanted(&name) {
+                    let mut contents = String::new();
+                    file.read_to_string(&mut contents)?;
+                    files.insert(name, contents);
+                }
+            }
+        }
+
+        Ok(Export { files })
+    }
+
+    fn parse<T>(&self, name: &str) -> Result<Vec<T>, Error> where T: DeserializeOwned {
+        match self.files.get(name) {
+            Some(contents) => serde_json::from_str(contents)
+                .map_err(|e| Error::from(format!("Could not parse {} from the export: {}", name, e))),
+            None => Err(Error::from(format!("No {} in the export", name)))
+        }
+    }
+
+    // Every plain message posted to the channel, in no particular order
+    fn messages(&self, channel_name: &str) -> Result<Vec<ExportMessage>, Error> {
+        let day_prefix = format!("{}/", channel_name);
+        let mut messages = vec![];
+        for name in self.files.keys().filter(|name| name.starts_with(&day_prefix)) {
+            messages.extend(self.parse::<ExportMessage>(name)?.into_iter().filter(|message| message.subtype.is_none()));
+        }
+        Ok(messages)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+fn to_attempt(message: ExportMessage, channel_id: &str) -> Option<Attempt> {
+    let (user_id, text, ts) = match (message.user, message.text, message.ts) {
+        (Some(user_id), Some(text), Some(ts)) => (user_id, text, ts),
+        _ => return None
+    };
+
+    Some(Attempt {
+        ts: types::parse_slack_ts(&ts)?,
+        successful: text == "deng",
+        // Replies in threads carry the timestamp of their parent message
+        thread_reply: message.thread_ts.map_or(false, |thread_ts| thread_ts != ts),
+        message_id: Some(MessageId { channel_id: String::from(channel_id), ts }),
+        deng_id: None,
+        user_id,
+    })
+}
+
+// Replays the listen channel from a Slack export with its original timestamps, and stores it unless this is a dry run.
+// Only messages from before the bot stored its first deng are replayed, so the two histories don't overlap
+pub fn run(conn: &PgConnection, config: &Config, path: &Path, dry_run: bool) -> Result<(), Error> {
+    let channel_name = dotenv!("LISTEN_CHANNEL_NAME");
+    let export = Export::read(path, channel_name)?;
+
+    let channel_id = export.parse::<ExportChannel>("channels.json")?
+        .into_iter()
+        .find(|channel| channel.name == channel_name)
+        .map(|channel| channel.id)
+        .ok_or_else(|| Error::from(format!("No #{} channel in the export", channel_name)))?;
+
+    let first_ts = storage::first_ts(conn)?;
+    let mut attempts = export.messages(channel_name)?
+        .into_iter()
+        .filter_map(|message| to_attempt(message, &channel_id))
+        .filter(|attempt| first_ts.map_or(true, |first_ts| attempt.ts < first_ts))
+        .collect::<Vec<_>>();
+    attempts.sort_by_key(|attempt| attempt.ts);
+
+    let mut replay = Replay::new(config, Rollovers::default(), HashSet::new());
+    let replayed = attempts.into_iter()
+        .map(|attempt| replay.attempt(attempt))
+        .collect::<Vec<_>>();
+
+    let successes = replayed.iter().filter(|attempt| attempt.deng.successful).count();
+    println!("Replayed {} attempts from #{}, {} of them dengs", replayed.len(), channel_name, successes);
+
+    if dry_run {
+        let users = export.parse::<ExportUser>("users.json")?
+            .into_iter()
+            .map(|user| (user.id, user.name))
+            .collect::<HashMap<_, _>>();
+        print_scoreboard(&replayed, &users, config);
+    } else {
+        let rows = replayed.iter()
+            .map(|attempt| (&attempt.deng, attempt.breakdown.as_slice()))
+            .collect::<Vec<_>>();
+        let stored = storage::store_replayed(conn, &rows, Source::Cli)?;
+        println!("Stored {} attempts ({} were already stored)", stored, replayed.len() - stored);
+    }
+
+    Ok(())
+}
+
+fn print_scoreboard(replayed: &[Replayed], users: &HashMap<String, String>, config: &Config) {
+    let dengs = replayed.iter()
+        .enumerate()
+        .map(|(index, attempt)| attempt.preview(index as i32))
+        .collect::<Vec<_>>();
+    let adjustments: Vec<Adjustment> = vec![];
+
+    for (index, standing) in scoreboard::tally(&dengs, &adjustments, &Penalties::from_config(config)).iter().enumerate() {
+        let name = users.get(&standing.user_id).unwrap_or(&standing.user_id);
+        println!("{:>3}. {:<20} {}", index + 1, name, standing.points);
+    }
+}
@@ -0,0 +1,167 @@
+extern crate csv;
+
+use chrono::NaiveDate;
+use diesel::PgConnection;
+use runner::DayCycle;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use storage::{self, OnConflict, Source};
+use types::{Adjustment, Deng, DengPoints, Error};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Table {
+    Dengs,
+    // Each deng's point breakdown
+    DengPoints,
+    Adjustments,
+}
+
+impl Table {
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "dengs" => Some(Table::Dengs),
+            "deng_points" => Some(Table::DengPoints),
+            "adjustments" => Some(Table::Adjustments),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Csv,
+    // One JSON object per line
+    JsonLines,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Self, Error> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("jsonl") => Ok(Format::JsonLines),
+            _ => Err(Error::from("Files must end in .csv or .jsonl"))
+        }
+    }
+}
+
+// Limits an export to a range of days, inclusive, and to a single user
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub user_id: Option<String>,
+}
+
+impl Filter {
+    // Days run from 9am, like everywhere else
+    fn times(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let from = self.from.map(|day| SystemTime::from(DayCycle::for_day(day).start()));
+        let to = self.to.map(|day| SystemTime::from(DayCycle::for_day(day.succ()).start()));
+        (from, to)
+    }
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::from(format!("Could not read or write CSV: {}", e))
+}
+
+fn write<T>(path: &Path, rows: &[T]) -> Result<(), Error> where T: Serialize {
+    // Checked before creating the file, so a bad name doesn't truncate whatever is already there
+    let format = Format::from_path(path)?;
+    let file = File::create(path)?;
+
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for row in rows {
+                writer.serialize(row).map_err(csv_error)?;
+            }
+            writer.flush()?;
+        },
+        Format::JsonLines => {
+            let mut writer = BufWriter::new(file);
+            for row in rows {
+                serde_json::to_writer(&mut writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read<T>(path: &Path) -> Result<Vec<T>, Error> where T: DeserializeOwned {
+    match Format::from_path(path)? {
+        Format::Csv => {
+            csv::Reader::from_path(path).map_err(csv_error)?
+                .deserialize()
+                .collect::<Result<Vec<T>, _>>()
+                .map_err(csv_error)
+        },
+        Format::JsonLines => {
+            let mut rows = vec![];
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    rows.push(serde_json::from_str(&line)?);
+                }
+            }
+            Ok(rows)
+        }
+    }
+}
+
+// Writes stored dengs, their point breakdowns or adjustments to a CSV or JSON Lines file, going by its extension
+pub fn export(conn: &PgConnection, table: Table, path: &Path, filter: &Filter) -> Result<(), Error> {
+    let (from, to) = filter.times();
+    let user_id = filter.user_id.as_ref().map(String::as_str);
+
+    let count = match table {
+        Table::Dengs => {
+            let dengs = storage::export_dengs(conn, from, to, user_id)?;
+            write(path, &dengs)?;
+            dengs.len()
+        },
+        Table::DengPoints => {
+            let points = storage::export_deng_points(conn, from, to, user_id)?;
+            write(path, &points)?;
+            points.len()
+        },
+        Table::Adjustments => {
+            let adjustments = storage::export_adjustments(conn, from, to, user_id)?;
+            write(path, &adjustments)?;
+            adjustments.len()
+        }
+    };
+
+    println!("Exported {} rows to {}", count, path.display());
+    Ok(())
+}
+
+// Loads a file written by `export` back in with its original IDs. Dengs should be imported before their
+// point breakdowns and the adjustments that void them
+pub fn import(conn: &PgConnection, table: Table, path: &Path, on_conflict: OnConflict) -> Result<(), Error> {
+    let (total, stored) = match table {
+        Table::Dengs => {
+            let dengs = read::<Deng>(path)?;
+            (dengs.len(), storage::import_dengs(conn, &dengs, on_conflict, Source::Cli)?)
+        },
+        Table::DengPoints => {
+            let points = read::<DengPoints>(path)?;
+            (points.len(), storage::import_deng_points(conn, &points, on_conflict, Source::Cli)?)
+        },
+        Table::Adjustments => {
+            let adjustments = read::<Adjustment>(path)?;
+            (adjustments.len(), storage::import_adjustments(conn, &adjustments, on_conflict, Source::Cli)?)
+        }
+    };
+
+    println!("Imported {} of {} rows from {} ({} were already stored)", stored, total, path.display(), total - stored);
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use config::Config;
+use diesel::PgConnection;
+use replay::{Attempt, Replay};
+use reqwest;
+use runner::Rollovers;
+use serde_json::{self, Value};
+use slack::api::MessageStandard;
+use std::collections::HashSet;
+use storage::{self, Source};
+use types::Error;
+
+// The most messages Slack will return in one page of history
+const PAGE_SIZE: &str = "200";
+
+#[derive(Deserialize)]
+struct HistoryResponse {
+    ok: bool,
+    error: Option<String>,
+    #[serde(default)]
+    messages: Vec<Value>,
+    #[serde(default)]
+    has_more: bool,
+    response_metadata: Option<ResponseMetadata>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMetadata {
+    next_cursor: Option<String>,
+}
+
+// Loads plain messages posted to a channel after the given Slack timestamp, oldest first.
+// Joins, edits and other subtypes are skipped, as they never reach the handler over RTM either
+pub fn history(api_url: &str, token: &str, channel_id: &str, oldest: &str) -> Result<Vec<MessageStandard>, Error> {
+    let client = reqwest::Client::new();
+    let url = format!("{}/conversations.history", api_url.trim_right_matches('/'));
+    let mut messages = vec![];
+    let mut cursor = String::new();
+
+    loop {
+        let response: HistoryResponse = client.get(&url)
+            .query(&[
+                ("token", token),
+                ("channel", channel_id),
+                ("oldest", oldest),
+                ("limit", PAGE_SIZE),
+                ("cursor", cursor.as_str()),
+            ])
+            .send()?
+            .json()?;
+
+        if !response.ok {
+            let reason = response.error.unwrap_or_else(|| String::from("no reason given"));
+            return Err(Error::from(format!("Could not load channel history: {}", reason)));
+        }
+
+        for message in response.messages {
+            if message.get("subtype").is_none() {
+                let mut message: MessageStandard = serde_json::from_value(message)?;
+                // History doesn't repeat the channel on each message
+                message.channel = Some(String::from(channel_id));
+                messages.push(message);
+            }
+        }
+
+        cursor = match response.response_metadata.and_then(|metadata| metadata.next_cursor) {
+            Some(ref next) if response.has_more && !next.is_empty() => next.clone(),
+            _ => break
+        };
+    }
+
+    // Slack returns the newest messages first
+    messages.reverse();
+    Ok(messages)
+}
+
+// Stores attempts missed on earlier days. Everything stored is replayed along with them, so their flags, placements
+// and streaks come out as if they had arrived on time. Returns how many were stored
+pub fn store_missed(conn: &PgConnection, config: &Config, rollovers: &Rollovers, missed: Vec<Attempt>) -> Result<usize, Error> {
+    let stored = storage::load_in_order(conn)?;
+    let stored_ids = stored.iter()
+        .filter_map(|deng| Attempt::from_deng(deng).message_id)
+        .collect::<HashSet<_>>();
+
+    let mut attempts = stored.iter()
+        .map(|deng| (false, Attempt::from_deng(deng)))
+        .chain(missed.into_iter()
+            .filter(|attempt| attempt.message_id.as_ref().map_or(true, |id| !stored_ids.contains(id)))
+            .map(|attempt| (true, attempt)))
+        .collect::<Vec<_>>();
+    attempts.sort_by_key(|&(_, ref attempt)| attempt.ts);
+
+    let mut replay = Replay::new(config, rollovers.clone(), storage::load_voided_ids(conn)?);
+    let replayed = attempts.into_iter()
+        .map(|(missed, attempt)| (missed, replay.attempt(attempt)))
+        .filter(|&(missed, _)| missed)
+        .map(|(_, replayed)| replayed)
+        .collect::<Vec<_>>();
+
+    let rows = replayed.iter()
+        .map(|attempt| (&attempt.deng, attempt.breakdown.as_slice()))
+        .collect::<Vec<_>>();
+    Ok(storage::store_replayed(conn, &rows, Source::Rtm)?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    // A stand-in for the Slack Web API that answers each request with the next body in turn,
+    // passing on the request line it was sent
+    pub fn serve(bodies: Vec<&'static str>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       body.len(), body).unwrap();
+                tx.send(String::from(request_line.trim())).unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    const NEWEST_PAGE: &str = r#"{"ok":true,"has_more":true,"response_metadata":{"next_cursor":"page2"},"messages":[
+        {"type":"message","user":"U2","text":"deng","ts":"1533000003.000300"},
+        {"type":"message","subtype":"channel_join","user":"U3","text":"<@U3> has joined the channel","ts":"1533000002.000200"}
+    ]}"#;
+    const OLDEST_PAGE: &str = r#"{"ok":true,"has_more":false,"messages":[
+        {"type":"message","user":"U1","text":"deng","ts":"1533000001.000100"}
+    ]}"#;
+
+    #[test]
+    fn pages_through_history_oldest_first() {
+        let (url, requests) = serve(vec![NEWEST_PAGE, OLDEST_PAGE]);
+
+        let messages = history(&url, "token", "C1", "1533000000.000000").unwrap();
+
+        let ts = messages.iter().map(|message| message.ts.clone().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ts, vec!["1533000001.000100", "1533000003.000300"]);
+        assert!(messages.iter().all(|message| message.channel == Some(String::from("C1"))));
+
+        let requests = requests.iter().take(2).collect::<Vec<_>>();
+        assert!(requests[0].starts_with("GET /api/conversations.history?"));
+        assert!(requests[0].contains("oldest=1533000000.000000"));
+        assert!(requests[1].contains("cursor=page2"));
+    }
+
+    #[test]
+    fn skips_messages_with_subtypes() {
+        let (url, _requests) = serve(vec![NEWEST_PAGE, OLDEST_PAGE]);
+
+        let messages = history(&url, "token", "C1", "0").unwrap();
+
+        assert!(messages.iter().all(|message| message.user != Some(String::from("U3"))));
+    }
+
+    #[test]
+    fn reports_slack_errors() {
+        let (url, _requests) = serve(vec![r#"{"ok":false,"error":"channel_not_found"}"#]);
+
+        let error = history(&url, "token", "C1", "0").unwrap_err();
+
+        assert!(format!("{}", error).contains("channel_not_found"));
+    }
+}
@@ -0,0 +1,191 @@
+extern crate png;
+
+use self::png::HasParameters;
+use types::Error;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const GRID: [u8; 3] = [225, 225, 225];
+const AXIS: [u8; 3] = [90, 90, 90];
+const MARGIN: u32 = 40;
+const GRID_LINES: u32 = 5;
+const LINE_WIDTH: i64 = 3;
+
+// One line on the chart, with a value for each point along the x axis
+pub struct Series {
+    pub color: [u8; 3],
+    pub values: Vec<i32>,
+}
+
+// Parses a Slack colour such as "#9f69e7", falling back to black
+pub fn parse_color(color: &str) -> [u8; 3] {
+    let hex = color.trim_left_matches('#');
+    let channel = |index: usize| hex.get(index..index + 2).and_then(|part| u8::from_str_radix(part, 16).ok());
+
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(red), Some(green), Some(blue)) => [red, green, blue],
+        _ => [0, 0, 0]
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        let pixels = (0..width * height).flat_map(|_| BACKGROUND.iter().cloned()).collect();
+        Canvas { width, height, pixels }
+    }
+
+    fn set(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let offset = ((y as u32 * self.width + x as u32) * 3) as usize;
+        self.pixels[offset..offset + 3].copy_from_slice(&color);
+    }
+
+    fn horizontal(&mut self, y: i64, from: i64, to: i64, color: [u8; 3]) {
+        for x in from..to + 1 {
+            self.set(x, y, color);
+        }
+    }
+
+    fn vertical(&mut self, x: i64, from: i64, to: i64, color: [u8; 3]) {
+        for y in from..to + 1 {
+            self.set(x, y, color);
+        }
+    }
+
+    // Bresenham's line, stamped with a square brush to give it some width
+    fn line(&mut self, (mut x, mut y): (i64, i64), (to_x, to_y): (i64, i64), color: [u8; 3]) {
+        let dx = (to_x - x).abs();
+        let dy = -(to_y - y).abs();
+        let step_x = if x < to_x { 1 } else { -1 };
+        let step_y = if y < to_y { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            for brush_x in -(LINE_WIDTH / 2)..LINE_WIDTH / 2 + 1 {
+                for brush_y in -(LINE_WIDTH / 2)..LINE_WIDTH / 2 + 1 {
+                    self.set(x + brush_x, y + brush_y, color);
+                }
+            }
+
+            if x == to_x && y == to_y {
+                break;
+            }
+
+            let doubled = error * 2;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()
+                .map_err(|e| Error::from(format!("Could not write PNG header: {}", e)))?;
+            writer.write_image_data(&self.pixels)
+                .map_err(|e| Error::from(format!("Could not write PNG data: {}", e)))?;
+        }
+        Ok(bytes)
+    }
+}
+
+// Draws each series as a line across the chart and returns it as a PNG.
+// Doesn't depend on Slack or the database, so charts can be written straight to disk to check them
+pub fn render(series: &[Series], width: u32, height: u32) -> Result<Vec<u8>, Error> {
+    if width <= MARGIN * 2 || height <= MARGIN * 2 {
+        return Err(Error::from("Chart is too small to draw"));
+    }
+
+    let mut canvas = Canvas::new(width, height);
+
+    let (left, top) = (MARGIN as i64, MARGIN as i64);
+    let (right, bottom) = ((width - MARGIN) as i64, (height - MARGIN) as i64);
+
+    for line in 0..GRID_LINES + 1 {
+        let y = top + (bottom - top) * line as i64 / GRID_LINES as i64;
+        canvas.horizontal(y, left, right, GRID);
+    }
+
+    let values = series.iter().flat_map(|series| series.values.iter().cloned());
+    let (min, max) = values.fold((0, 0), |(min, max), value| (min.min(value), max.max(value)));
+    let range = if max > min { (max - min) as i64 } else { 1 };
+    let points = series.iter().map(|series| series.values.len()).max().unwrap_or(0);
+
+    let position = |index: usize, value: i32| {
+        let x = match points {
+            0 | 1 => left,
+            _ => left + (right - left) * index as i64 / (points - 1) as i64
+        };
+        let y = bottom - (bottom - top) * (value - min) as i64 / range;
+        (x, y)
+    };
+
+    for series in series {
+        let mut previous = None;
+        for (index, &value) in series.values.iter().enumerate() {
+            let point = position(index, value);
+            canvas.line(previous.unwrap_or(point), point, series.color);
+            previous = Some(point);
+        }
+    }
+
+    // Axes go on last so the lines never cover them
+    canvas.vertical(left, top, bottom, AXIS);
+    canvas.horizontal(position(0, 0).1, left, right, AXIS);
+
+    canvas.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::png;
+    use std::env;
+    use std::fs::{self, File};
+
+    fn pixel(data: &[u8], width: u32, x: u32, y: u32) -> [u8; 3] {
+        let offset = ((y * width + x) * 3) as usize;
+        [data[offset], data[offset + 1], data[offset + 2]]
+    }
+
+    #[test]
+    fn renders_a_readable_png() {
+        let red = parse_color("#ff0000");
+        let bytes = render(&[Series { color: red, values: vec![0, 10] }], 200, 120).unwrap();
+
+        let path = env::temp_dir().join("dengbot-chart-test.png");
+        fs::write(&path, &bytes).unwrap();
+
+        let (info, mut reader) = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!((info.width, info.height), (200, 120));
+        assert_eq!(info.color_type, png::ColorType::RGB);
+        assert_eq!(pixel(&data, 200, 5, 5), BACKGROUND);
+        // The line runs from the bottom left to the top right of the plot, through its centre
+        assert_eq!(pixel(&data, 200, 100, 60), red);
+        assert_eq!(pixel(&data, 200, 40, 60), AXIS);
+    }
+
+    #[test]
+    fn refuses_charts_too_small_to_draw() {
+        assert!(render(&[], MARGIN * 2, 100).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use backfill;
+use backup::{self, Filter, Table};
+use chrono::NaiveDate;
+use config::Config;
+use diesel::PgConnection;
+use recompute;
+use std::path::Path;
+use storage::OnConflict;
+use types::Error;
+
+const USAGE: &str = "Usage:
+    dengbot                                          Run the bot
+    dengbot backfill <export zip or directory> [--dry-run]
+    dengbot recompute [--write]
+    dengbot export <dengs|deng_points|adjustments> <file.csv|file.jsonl> [--from=YYYY-MM-DD] [--to=YYYY-MM-DD] [--user=<user ID>]
+    dengbot import <dengs|deng_points|adjustments> <file.csv|file.jsonl> [--on-conflict=skip|fail]";
+
+// Maintenance commands, run from the command line in place of the bot
+pub fn run(args: &[String], conn: &PgConnection, config: &Config) -> Result<(), Error> {
+    let flags = args.iter()
+        .filter(|arg| arg.starts_with("--"))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let mut params = args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(String::as_str);
+
+    match params.next() {
+        Some("backfill") => {
+            let path = params.next().ok_or_else(|| Error::from(USAGE))?;
+            backfill::run(conn, config, Path::new(path), flags.contains(&"--dry-run"))
+        },
+        Some("recompute") => recompute::run(conn, config, flags.contains(&"--write")),
+        Some("export") => {
+            let table = params.next().and_then(Table::from_param).ok_or_else(|| Error::from(USAGE))?;
+            let path = params.next().ok_or_else(|| Error::from(USAGE))?;
+            let filter = Filter {
+                from: parse_date(flag_value(&flags, "--from"))?,
+                to: parse_date(flag_value(&flags, "--to"))?,
+                user_id: flag_value(&flags, "--user").map(String::from),
+            };
+            backup::export(conn, table, Path::new(path), &filter)
+        },
+        Some("import") => {
+            let table = params.next().and_then(Table::from_param).ok_or_else(|| Error::from(USAGE))?;
+            let path = params.next().ok_or_else(|| Error::from(USAGE))?;
+            let on_conflict = match flag_value(&flags, "--on-conflict") {
+                None | Some("skip") => OnConflict::Skip,
+                Some("fail") => OnConflict::Fail,
+                Some(other) => return Err(Error::from(format!("Unknown conflict handling '{}', expected skip or fail", other)))
+            };
+            backup::import(conn, table, Path::new(path), on_conflict)
+        },
+        _ => Err(Error::from(USAGE))
+    }
+}
+
+// Flags with values are written `--name=value`
+fn flag_value<'a>(flags: &[&'a str], name: &str) -> Option<&'a str> {
+    let prefix = format!("{}=", name);
+    flags.iter()
+        .cloned()
+        .find(|flag| flag.starts_with(&prefix))
+        .map(|flag| &flag[prefix.len()..])
+}
+
+fn parse_date(date: Option<&str>) -> Result<Option<NaiveDate>, Error> {
+    match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|e| Error::from(format!("Invalid date '{}', expected YYYY-MM-DD: {}", date, e))),
+        None => Ok(None)
+    }
+}
@@ -2,36 +2,252 @@ extern crate url;
 
 use futures::{self, Stream, future::Future};
 use serde_json;
-use storage;
+use storage::{self, Source};
 use std::collections::HashMap;
-use hyper::{self, Response, Request, server::Service, StatusCode};
-use types::{DbConnection, Deng, Error, SlackInfo};
+use hyper::{self, Method, Response, Request, server::Service, StatusCode};
+use hyper::header::{CacheControl, CacheDirective, ContentType, LastEventId};
+use events::{DengEvent, EventStream};
+use admin::{self, Outcome};
+use announce;
+use config::SharedConfig;
+use subcommand::{Subcommand, USAGE};
+use scoreboard::{self, Penalties, Period, Standing, UserDisplay};
+use types::{DbPool, Error, SlackInfo};
+use diesel::PgConnection;
+use runner::{DayCycle, Rollovers};
+use seasons;
+use achievements;
+use ratings;
+use teams;
+use stats::{self, UserStats};
+use chart::{self, Series};
+use std::thread;
+use analytics::Heatmap;
+use metrics;
+use streaks::{self, SkipDays, Streak};
+use web;
 use slack;
 use slack_hook::{self, Attachment, AttachmentBuilder, PayloadBuilder};
 
 const SLACK_TOKEN_PARAM_NAME: &str = "token";
+const SLACK_TEXT_PARAM_NAME: &str = "text";
+const SLACK_USER_ID_PARAM_NAME: &str = "user_id";
+const SLACK_CHANNEL_ID_PARAM_NAME: &str = "channel_id";
+const USER_PAGE_PREFIX: &str = "/leaderboard/user/";
+const HEATMAP_API_PATH: &str = "/api/heatmap";
+const MAX_REPLAYED_EVENTS: i64 = 500;
+const SPARKLINE_DAYS: i64 = 30;
+const CHART_WIDTH: u32 = 800;
+const CHART_HEIGHT: u32 = 400;
 
+// Slack posts commands to us, everything else is a browser looking at the leaderboard
+enum Route {
+    Command,
+    Leaderboard(Period),
+    UserPage(String),
+    Events(Option<i32>),
+    HeatmapApi(Option<String>),
+    Metrics,
+    NotFound
+}
+
+impl Route {
+    fn from_request(req: &Request) -> Self {
+        match (req.method(), req.path()) {
+            (&Method::Post, _) => Route::Command,
+            (&Method::Get, "/") | (&Method::Get, "/leaderboard") => {
+                let period = req.query()
+                    .and_then(|query| url::form_urlencoded::parse(query.as_bytes())
+                        .find(|&(ref key, _)| key == "period")
+                        .and_then(|(_, value)| Period::from_param(&value)))
+                    .unwrap_or(Period::AllTime);
+
+                Route::Leaderboard(period)
+            },
+            (&Method::Get, "/events") => {
+                let last_event_id = req.headers().get::<LastEventId>()
+                    .and_then(|&LastEventId(ref id)| id.parse().ok());
+
+                Route::Events(last_event_id)
+            },
+            (&Method::Get, "/metrics") => Route::Metrics,
+            (&Method::Get, HEATMAP_API_PATH) => {
+                let user_id = req.query()
+                    .and_then(|query| url::form_urlencoded::parse(query.as_bytes())
+                        .find(|&(ref key, _)| key == "user")
+                        .map(|(_, value)| value.into_owned()));
+
+                Route::HeatmapApi(user_id)
+            },
+            (&Method::Get, path) if path.starts_with(USER_PAGE_PREFIX) => {
+                Route::UserPage(String::from(&path[USER_PAGE_PREFIX.len()..]))
+            },
+            _ => Route::NotFound
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct CommandListener {
     info: SlackInfo,
-    db_conn: DbConnection
+    db_pool: DbPool,
+    events: EventStream,
+    config: SharedConfig
 }
 
 impl CommandListener {
-    pub fn new(info: SlackInfo, db_conn: DbConnection) -> Self {
+    pub fn new(info: SlackInfo, db_pool: DbPool, events: EventStream, config: SharedConfig) -> Self {
         Self {
             info,
-            db_conn
+            db_pool,
+            events,
+            config
+        }
+    }
+
+    fn run_command(&self, user_id: &str, channel_id: &str, text: &str) -> Result<CommandResponse, Error> {
+        let subcommand = match Subcommand::parse(text) {
+            Ok(subcommand) => subcommand,
+            Err(usage) => return Ok(Self::build_text_payload(&usage)?.ephemeral())
+        };
+
+        if subcommand.requires_admin() && !self.config.get().is_admin(user_id) {
+            warn!("Non-admin {} tried to run admin command {:?}", user_id, subcommand);
+            return Ok(Self::build_text_payload("Only dengbot admins can do that.")?.ephemeral());
+        }
+
+        let conn = self.db_pool.get()?;
+
+        let outcome = match subcommand {
+            Subcommand::Scoreboard => return self.build_scoreboard_payload(&conn),
+            Subcommand::Streaks => {
+                let streaks = streaks::by_user(&storage::load(&conn)?,
+                                               &storage::load_voided_ids(&conn)?,
+                                               &Rollovers::load(&conn)?,
+                                               DayCycle::today(),
+                                               &SkipDays::from_config(&self.config.get()));
+                return Self::build_text_payload(&streaks::format_streaks(&streaks));
+            },
+            Subcommand::Hot => {
+                let half_life_hours = self.config.get().hot_half_life_hours;
+                let text = scoreboard::format_hot(&storage::load_hot_scores(&conn, half_life_hours)?, half_life_hours);
+                return Self::build_text_payload(&text);
+            },
+            Subcommand::Teams => {
+                let since = storage::current_season(&conn)?.map(|season| season.started);
+                let standings = teams::tally(&storage::load_since(&conn, since)?,
+                                             &storage::load_adjustments(&conn, since)?,
+                                             &Penalties::from_config(&self.config.get()),
+                                             &storage::load_team_members(&conn)?);
+                return Self::build_text_payload(&teams::format_standings("Team Standings", &standings));
+            },
+            Subcommand::Chart { days } => {
+                // Drawing and uploading can take longer than Slack will wait for a response
+                let listener = self.clone();
+                let channel_id = String::from(channel_id);
+                thread::spawn(move || {
+                    if let Err(e) = listener.post_chart(&channel_id, days) {
+                        error!("Could not post chart: {}", e);
+                    }
+                });
+                return Ok(Self::build_text_payload("Drawing your chart...")?.ephemeral());
+            },
+            Subcommand::Heatmap { user } => {
+                let text = match user {
+                    Some(user) => match self.info.find_user_id(&user) {
+                        Some(target) => self.build_heatmap(&conn, Some(&target))?.format(&format!("When <@{}> dengs", target)),
+                        None => return Ok(Self::build_text_payload(&format!("Could not find user {}.", user))?.ephemeral())
+                    },
+                    None => self.build_heatmap(&conn, None)?.format("When everyone dengs")
+                };
+                return Self::build_text_payload(&text);
+            },
+            Subcommand::Me => return Ok(self.build_me_payload(&conn, user_id)?.ephemeral()),
+            Subcommand::Vs { first, second } => {
+                return match (self.info.find_user_id(&first), self.info.find_user_id(&second)) {
+                    (Some(first), Some(second)) => self.build_vs_payload(&conn, &first, &second),
+                    (None, _) => Ok(Self::build_text_payload(&format!("Could not find user {}.", first))?.ephemeral()),
+                    (_, None) => Ok(Self::build_text_payload(&format!("Could not find user {}.", second))?.ephemeral())
+                };
+            },
+            Subcommand::Help => return Ok(Self::build_text_payload(USAGE)?.ephemeral()),
+            Subcommand::Void { deng_id, reason } => admin::void(&conn, user_id, deng_id, reason, Source::SlashCommand)?,
+            Subcommand::Grant { user, points, reason } => match self.info.find_user_id(&user) {
+                Some(target) => admin::adjust(&conn, user_id, target, points, reason, Source::SlashCommand)?,
+                None => Outcome::Reject(format!("Could not find user {}.", user))
+            },
+            Subcommand::Deduct { user, points, reason } => match self.info.find_user_id(&user) {
+                Some(target) => admin::adjust(&conn, user_id, target, -points, reason, Source::SlashCommand)?,
+                None => Outcome::Reject(format!("Could not find user {}.", user))
+            },
+            Subcommand::Undo => admin::undo(&conn, user_id, Source::SlashCommand)?,
+            Subcommand::Reload => admin::reload_config(&conn, user_id, &self.config, Source::SlashCommand)?,
+            Subcommand::Audit { count } => {
+                let text = admin::format_audit_log(&storage::load_audit_log(&conn, count)?);
+                return Ok(Self::build_text_payload(&text)?.ephemeral());
+            },
+            Subcommand::Seasons => {
+                let past = storage::load_past_seasons(&conn)?;
+                let ids = past.iter().map(|season| season.id).collect::<Vec<_>>();
+                let text = seasons::format_seasons(&past, &storage::load_season_standings(&conn, &ids)?);
+                return Self::build_text_payload(&text);
+            },
+            Subcommand::Season { season_id } => {
+                let text = match storage::find_season(&conn, season_id)? {
+                    Some(ref season) if season.ended.is_some() => {
+                        seasons::format_standings(season, &storage::load_season_standings(&conn, &[season.id])?)
+                    },
+                    Some(_) => String::from("That season is still in progress. Use `/deng` to see the current standings."),
+                    None => format!("There is no season with ID {}.", season_id)
+                };
+                return Ok(Self::build_text_payload(&text)?.ephemeral());
+            },
+            Subcommand::EndSeason { name } => admin::end_season(&conn, user_id, name, &self.config.get(), Source::SlashCommand)?,
+            Subcommand::Rating => {
+                if !self.config.get().ratings_enabled {
+                    return Ok(Self::build_text_payload("Ratings are not enabled.")?.ephemeral());
+                }
+                return Self::build_text_payload(&ratings::format_ratings(&storage::load_ratings(&conn)?));
+            },
+            Subcommand::RecomputeRatings => admin::recompute_ratings(&conn, user_id, &self.config.get(), Source::SlashCommand)?
+        };
+
+        match outcome {
+            Outcome::Announce(announcement) => {
+                if let Err(e) = announce::post(&self.info.meta_channel_id, &announcement) {
+                    error!("Could not announce admin change: {}", e);
+                }
+                Ok(Self::build_text_payload(&announcement)?.ephemeral())
+            },
+            Outcome::Reject(reason) => Ok(Self::build_text_payload(&reason)?.ephemeral())
         }
     }
 
-    fn build_scoreboard_message(&self) -> Result<String, Error> {
-        let dengs = storage::load(&self.db_conn).map_err(Error::from)?;
-        let message = Self::build_scoreboard_payload(&self.info, &dengs)?;
-        serde_json::to_string(&message).map_err(Error::from)
+    fn build_text_payload(text: &str) -> Result<CommandResponse, Error> {
+        PayloadBuilder::new()
+            .text(text)
+            .build()
+            .map(|payload| payload.into())
+            .map_err(Error::from)
     }
 
-    fn build_scoreboard_payload(info: &SlackInfo, dengs: &[Deng]) -> Result<CommandResponse, Error> {
-        match dengs.len() {
+    fn build_scoreboard_payload(&self, conn: &PgConnection) -> Result<CommandResponse, Error> {
+        // The scoreboard only covers the current season, but streaks carry across seasons
+        let dengs = storage::load(conn)?;
+        let season = storage::current_season(conn)?;
+        let since = season.as_ref().map(|season| season.started);
+        let penalties = Penalties::from_config(&self.config.get());
+        let standings = scoreboard::tally(dengs.iter().filter(|deng| since.map_or(true, |since| deng.ts >= since)),
+                                          &storage::load_adjustments(conn, since)?,
+                                          &penalties);
+        let streaks = streaks::by_user(&dengs,
+                                       &storage::load_voided_ids(conn)?,
+                                       &Rollovers::load(conn)?,
+                                       DayCycle::today(),
+                                       &SkipDays::from_config(&self.config.get()));
+        let badges = achievements::badges_by_user(&storage::load_achievements(conn)?);
+
+        match standings.len() {
             0 => {
                 info!("No scoreboard info found - returning default.");
 
@@ -42,7 +258,7 @@ impl CommandListener {
                     .map_err(Error::from)
             },
             _ => {
-                let attachments = Self::create_scoreboard_attachments(dengs, &info.users)
+                let attachments = Self::create_scoreboard_attachments(&standings, &streaks, &badges, &penalties, &self.info.users)
                     .into_iter()
                     .filter_map(|attachment| match attachment {
                         Ok(attach) => Some(attach),
@@ -53,8 +269,13 @@ impl CommandListener {
                     })
                     .collect();
 
+                let title = match season {
+                    Some(season) => format!(":jewdave: *Deng Champions - {}* :jewdave:", season.name),
+                    None => String::from(":jewdave: *Deng Champions* :jewdave:")
+                };
+
                 PayloadBuilder::new()
-                    .text(":jewdave: *Deng Champions* :jewdave:")
+                    .text(title.as_str())
                     .attachments(attachments)
                     .build()
                     .map(|payload| payload.into())
@@ -63,53 +284,295 @@ impl CommandListener {
         }
     }
 
-    fn create_scoreboard_attachments(dengs: &[Deng],
+    fn create_scoreboard_attachments(standings: &[Standing],
+                                     streaks: &HashMap<String, Streak>,
+                                     badges: &HashMap<String, String>,
+                                     penalties: &Penalties,
                                      user_list: &[slack::User]) -> Vec<Result<Attachment, Error>> {
-        let mut ordered_scores = dengs
-            .iter()
-            .filter(|deng| deng.successful)
-            .fold(HashMap::new(), |mut map, deng| {
-                *map.entry(&deng.user_id).or_insert(0) += deng.value();
-                map
+        standings.iter()
+            .map(|standing| {
+                let user = UserDisplay::find(user_list, &standing.user_id)?;
+
+                // Penalties get their own column, between the score and the name
+                let score = if penalties.enabled() {
+                    format!("*{}*\t_-{}_\t\t", standing.points, standing.penalty)
+                } else {
+                    format!("*{}*\t\t\t", standing.points)
+                };
+
+                let mut formatted_msg = match user.username.len() {
+                    0 => format!("{}*{}*", score, user.full_name),
+                    _ => format!("{}*{}* ({})", score, user.username, user.full_name)
+                };
+
+                if let Some(badges) = badges.get(&standing.user_id) {
+                    formatted_msg.push_str(&format!(" {}", badges));
+                }
+
+                match streaks.get(&standing.user_id) {
+                    Some(streak) if streak.current > 1 => formatted_msg.push_str(&format!("\t:fire: {}", streak.current)),
+                    _ => {}
+                }
+
+                AttachmentBuilder::new(formatted_msg)
+                    .color(user.color.as_str())
+                    .build()
+                    .map_err(Error::from)
             })
-            .into_iter()
+            .collect()
+    }
+
+    fn load_user_stats(&self, conn: &PgConnection, user_id: &str) -> Result<UserStats, Error> {
+        let config = self.config.get();
+        Ok(UserStats::calculate(user_id,
+                                &storage::load_user(conn, user_id)?,
+                                &storage::load_user_adjustments(conn, user_id)?,
+                                &Penalties::from_config(&config),
+                                &SkipDays::from_config(&config)))
+    }
+
+    fn build_vs_payload(&self, conn: &PgConnection, first_id: &str, second_id: &str) -> Result<CommandResponse, Error> {
+        let first = self.load_user_stats(conn, first_id)?;
+        let second = self.load_user_stats(conn, second_id)?;
+
+        let attachments = vec![
+            self.create_vs_attachment(&first, &second)?,
+            self.create_vs_attachment(&second, &first)?
+        ];
+
+        PayloadBuilder::new()
+            .text(format!(":crossed_swords: <@{}> vs <@{}> :crossed_swords:", first_id, second_id).as_str())
+            .attachments(attachments)
+            .build()
+            .map(|payload| payload.into())
+            .map_err(Error::from)
+    }
+
+    fn create_vs_attachment(&self, stats: &UserStats, opponent: &UserStats) -> Result<Attachment, Error> {
+        // Users who have left Slack can still be compared, just without their colour
+        let (name, color) = match UserDisplay::find(&self.info.users, &stats.user_id) {
+            Ok(user) => (user.full_name, user.color),
+            Err(_) => (format!("<@{}>", stats.user_id), String::from("#000000"))
+        };
+
+        let average = stats.average_time().map_or(String::from("never denged"), stats::format_duration);
+
+        let text = format!("*{}*\nPoints: *{}*\nStreak: {} (best {})\nFirst of the day: {}\n\
+                            Average time after rollover: {}\nDays ahead of <@{}>: {}",
+                           name, stats.points, stats.streak.current, stats.streak.longest, stats.first_of_day,
+                           average, opponent.user_id, stats.days_ahead_of(opponent));
+
+        AttachmentBuilder::new(text)
+            .color(color.as_str())
+            .build()
+            .map_err(Error::from)
+    }
+
+    fn build_me_payload(&self, conn: &PgConnection, user_id: &str) -> Result<CommandResponse, Error> {
+        let stats = self.load_user_stats(conn, user_id)?;
+
+        let standings = scoreboard::tally(&storage::load(conn)?,
+                                          &storage::load_adjustments(conn, None)?,
+                                          &Penalties::from_config(&self.config.get()));
+        let rank = standings.iter()
+            .position(|standing| standing.user_id == user_id)
+            .map_or(String::from("unranked"), |index| format!("#{} of {}", index + 1, standings.len()));
+
+        let color = UserDisplay::find(&self.info.users, user_id)
+            .map(|user| user.color)
+            .unwrap_or_else(|_| String::from("#000000"));
+
+        let time = |duration: Option<::chrono::Duration>| duration.map_or(String::from("-"), stats::format_duration);
+
+        let text = format!("Points: *{}* ({})\nSuccessful dengs: {}\nFailed attempts: {}\nFirst of the day: {}\n\
+                            Streak: {} (best {})\nAverage time after rollover: {}\nFastest time after rollover: {}\n\
+                            Favourite day: {}\nLast {} days: `{}`",
+                           stats.points, rank, stats.successes, stats.failures, stats.first_of_day,
+                           stats.streak.current, stats.streak.longest, time(stats.average_time()), time(stats.fastest_time()),
+                           stats.favourite_weekday().map_or(String::from("-"), |weekday| format!("{:?}", weekday)),
+                           SPARKLINE_DAYS, stats.sparkline(SPARKLINE_DAYS));
+
+        let attachment = AttachmentBuilder::new(text)
+            .color(color.as_str())
+            .build()?;
+
+        PayloadBuilder::new()
+            .text(format!(":bar_chart: *Deng stats for <@{}>*", user_id).as_str())
+            .attachments(vec![attachment])
+            .build()
+            .map(|payload| payload.into())
+            .map_err(Error::from)
+    }
+
+    fn post_chart(&self, channel_id: &str, days: i64) -> Result<(), Error> {
+        let conn = self.db_pool.get()?;
+        let config = self.config.get();
+
+        let today = DayCycle::today();
+        let chart_days = (0..days).rev().map(|ago| today - ::chrono::Duration::days(ago)).collect::<Vec<_>>();
+        let history = scoreboard::history(&storage::load(&conn)?,
+                                          &storage::load_adjustments(&conn, None)?,
+                                          &Penalties::from_config(&config),
+                                          &chart_days);
+
+        let mut leaders = history.into_iter().collect::<Vec<_>>();
+        leaders.sort_by_key(|&(_, ref values)| -values.last().cloned().unwrap_or(0));
+        leaders.truncate(config.chart_top_users);
+
+        // Lines use the same colours as the scoreboard, and the legend lists them
+        let colors = leaders.iter()
+            .map(|&(ref user_id, _)| UserDisplay::find(&self.info.users, user_id).map(|user| user.color).unwrap_or_default())
             .collect::<Vec<_>>();
 
-        ordered_scores.sort_by(|first, second| second.1.cmp(&first.1));
+        let legend = leaders.iter()
+            .zip(colors.iter())
+            .map(|(&(ref user_id, ref values), color)| format!("<@{}> {} ({})", user_id, values.last().cloned().unwrap_or(0), color))
+            .collect::<Vec<_>>()
+            .join("\n");
 
-        trace!("Raw ordered score list: {:?}", ordered_scores);
+        let series = leaders.into_iter()
+            .zip(colors.iter())
+            .map(|((_, values), color)| Series { color: chart::parse_color(color), values })
+            .collect::<Vec<_>>();
 
-        ordered_scores.into_iter()
-            .map(|(user_id, score)| {
-                let user = &user_list.iter()
-                    .find(|user| match user.id {
-                        Some(ref id) => id == user_id,
-                        None => false,
-                    })
-                    .ok_or_else(|| Error::from("Could not find matching user"))?;
+        let image = chart::render(&series, CHART_WIDTH, CHART_HEIGHT)?;
+        let comment = format!(":chart_with_upwards_trend: *Points over the last {} days*\n{}", days, legend);
+        announce::upload(&config.slack_api_url, channel_id, "deng-chart.png", image, &comment)
+    }
 
-                let profile = user.profile.as_ref()
-                    .ok_or_else(|| Error::from("Could not find user profile"))?;
+    fn build_leaderboard_page(&self, period: Period) -> Result<String, Error> {
+        let conn = self.db_pool.get()?;
+        let dengs = storage::load_since(&conn, period.since())?;
+        let adjustments = storage::load_adjustments(&conn, period.since())?;
+        let penalties = Penalties::from_config(&self.config.get());
+        Ok(web::leaderboard_page(&self.info, period, &scoreboard::tally(&dengs, &adjustments, &penalties)))
+    }
 
-                let username = profile.display_name.as_ref()
-                    .ok_or_else(|| Error::from("Could not find username"))?;
+    fn build_user_page(&self, user_id: &str) -> Result<String, Error> {
+        let conn = self.db_pool.get()?;
+        let dengs = storage::load_user(&conn, user_id)?;
+        let adjustments = storage::load_user_adjustments(&conn, user_id)?;
+        let breakdowns = storage::load_breakdowns(&conn, &dengs.iter().map(|deng| deng.id).collect::<Vec<_>>())?;
+        let penalties = Penalties::from_config(&self.config.get());
+        Ok(web::user_page(&self.info, user_id, &dengs, &adjustments, &breakdowns, &penalties))
+    }
 
-                let full_name = profile.real_name.as_ref()
-                    .ok_or_else(|| Error::from("Could not find username"))?;
+    fn build_heatmap(&self, conn: &PgConnection, user_id: Option<&str>) -> Result<Heatmap, Error> {
+        let dengs = match user_id {
+            Some(user_id) => storage::load_user(conn, user_id)?,
+            None => storage::load(conn)?
+        };
+        Ok(Heatmap::from_dengs(&dengs))
+    }
 
-                let hex_color = format!("#{}", user.color.as_ref().unwrap_or(&String::from("000000")));
+    fn heatmap_response(&self, user_id: Option<&str>) -> Response {
+        let json = self.db_pool.get()
+            .map_err(Error::from)
+            .and_then(|conn| self.build_heatmap(&conn, user_id))
+            .and_then(|heatmap| serde_json::to_string(&heatmap).map_err(Error::from));
 
-                let formatted_msg = match username.len() {
-                    0 => format!("*{}*\t\t\t*{}*", score, full_name),
-                    _ => format!("*{}*\t\t\t*{}* ({})", score, username, full_name)
-                };
+        match json {
+            Ok(json) => Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentType::json())
+                .with_body(json),
+            Err(e) => {
+                error!("Could not build heatmap: {}", e);
+                Response::new().with_status(StatusCode::InternalServerError)
+            }
+        }
+    }
 
-                AttachmentBuilder::new(formatted_msg)
-                    .color(hex_color.as_str())
-                    .build()
-                    .map_err(Error::from)
-            })
-            .collect()
+    fn page_response(page: Result<String, Error>) -> Response {
+        match page {
+            Ok(page) => Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentType::html())
+                .with_body(page),
+            Err(e) => {
+                error!("Could not build web page: {}", e);
+                Response::new().with_status(StatusCode::InternalServerError)
+            }
+        }
+    }
+
+    fn open_event_stream(&self, last_event_id: Option<i32>) -> Response {
+        // Subscribe before loading missed events, so anything stored in between still reaches the client
+        let (client_id, body) = self.events.subscribe(last_event_id.is_some(), MAX_REPLAYED_EVENTS as usize + 1);
+
+        if let Some(id) = last_event_id {
+            // One more than we replay, to tell whether there were too many
+            let missed = self.db_pool.get()
+                .map_err(Error::from)
+                .and_then(|conn| storage::load_after(&conn, id, MAX_REPLAYED_EVENTS + 1).map_err(Error::from));
+
+            let (missed, truncated) = match missed {
+                Ok(dengs) => {
+                    let truncated = dengs.len() as i64 > MAX_REPLAYED_EVENTS;
+                    let events = dengs.iter()
+                        .take(MAX_REPLAYED_EVENTS as usize)
+                        .map(DengEvent::from_deng)
+                        .collect::<Vec<_>>();
+                    (events, truncated)
+                },
+                Err(e) => {
+                    error!("Could not load missed events after {}: {}", id, e);
+                    (vec![], false)
+                }
+            };
+
+            self.events.replay(client_id, &missed, truncated);
+        }
+
+        Response::new()
+            .with_status(StatusCode::Ok)
+            .with_header(ContentType(hyper::mime::TEXT_EVENT_STREAM))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+            .with_body(body)
+    }
+
+    fn handle_command(&self, body_chunk: &hyper::Chunk) -> Response {
+        match String::from_utf8(body_chunk.to_vec()) {
+            Ok(body) => {
+                debug!("Received Slack command body: {}", body);
+
+                let params = url::form_urlencoded::parse(body.as_ref())
+                    .into_owned()
+                    .collect::<HashMap<String, String>>();
+
+                match params.get(SLACK_TOKEN_PARAM_NAME) {
+                    Some(val) => {
+                        info!("Received command from Slack. Verifying command token...");
+
+                        if val == dotenv!("SLACK_COMMAND_VERIFICATION_KEY") {
+                            info!("Successfully verified command token. Sending response.");
+
+                            let user_id = params.get(SLACK_USER_ID_PARAM_NAME).map(String::as_str).unwrap_or("");
+                            let channel_id = params.get(SLACK_CHANNEL_ID_PARAM_NAME).map(String::as_str).unwrap_or("");
+                            let text = params.get(SLACK_TEXT_PARAM_NAME).map(String::as_str).unwrap_or("");
+
+                            let message = self.run_command(user_id, channel_id, text)
+                                .and_then(|response| serde_json::to_string(&response).map_err(Error::from));
+
+                            match message {
+                                Ok(msg) => return Response::new()
+                                    .with_status(StatusCode::Ok)
+                                    .with_header(ContentType::json())
+                                    .with_body(msg),
+                                Err(e) => error!("Could not build command response: {}", e)
+                            }
+                        } else {
+                            error!("Could not validate command token!");
+                        }
+                    },
+                    None => error!("Could not find token parameter in Slack POST.")
+                }
+            },
+            Err(e) => error!("Could not create string from request body: {}", e)
+        }
+
+        // fall back to server error response
+        Response::new().with_status(StatusCode::InternalServerError)
     }
 }
 
@@ -120,58 +583,44 @@ impl Service for CommandListener {
     type Future = Box<Future<Item=Self::Response, Error=Self::Error>>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        // TODO: build payload AFTER verifying token
-        let msg = match self.build_scoreboard_message() {
-            Ok(msg) => msg,
-            Err(e) => {
-                error!("Could not build scoreboard message: {}", e);
-                return Box::new(futures::future::ok(hyper::Response::new()
-                    .with_status(StatusCode::InternalServerError)));
-            }
-        };
+        let route = Route::from_request(&req);
 
-        Box::new(req.body().concat2().and_then(move |body_chunk| {
-            match String::from_utf8(body_chunk.to_vec()) {
-                Ok(body) => {
-                    debug!("Received Slack command body: {}", body);
-
-                    let params = url::form_urlencoded::parse(body.as_ref())
-                        .into_owned()
-                        .collect::<HashMap<String, String>>();
-
-                    match params.get(SLACK_TOKEN_PARAM_NAME) {
-                        Some(val) => {
-                            info!("Received command from Slack. Verifying command token...");
-
-                            if val == dotenv!("SLACK_COMMAND_VERIFICATION_KEY") {
-                                info!("Successfully verified command token. Sending response.");
-
-                                return futures::future::ok(
-                                    hyper::Response::new()
-                                        .with_status(StatusCode::Ok)
-                                        .with_header(hyper::header::ContentType::json())
-                                        .with_body(msg))
-                            } else {
-                                error!("Could not validate command token!");
-                            }
-                        },
-                        None => error!("Could not find token parameter in Slack POST.")
-                    }
-                },
-                Err(e) => error!("Could not create string from request body: {}", e)
+        match route {
+            Route::Command => {
+                let listener = self.clone();
+                Box::new(req.body().concat2().map(move |body_chunk| listener.handle_command(&body_chunk)))
+            },
+            Route::Leaderboard(period) => {
+                Box::new(futures::future::ok(Self::page_response(self.build_leaderboard_page(period))))
+            },
+            Route::UserPage(user_id) => {
+                Box::new(futures::future::ok(Self::page_response(self.build_user_page(&user_id))))
+            },
+            Route::Events(last_event_id) => {
+                Box::new(futures::future::ok(self.open_event_stream(last_event_id)))
+            },
+            Route::Metrics => {
+                Box::new(futures::future::ok(Response::new()
+                    .with_status(StatusCode::Ok)
+                    .with_header(ContentType::plaintext())
+                    .with_body(metrics::render())))
+            },
+            Route::HeatmapApi(user_id) => {
+                Box::new(futures::future::ok(self.heatmap_response(user_id.as_ref().map(String::as_str))))
+            },
+            Route::NotFound => {
+                Box::new(futures::future::ok(Response::new()
+                    .with_status(StatusCode::NotFound)
+                    .with_header(ContentType::html())
+                    .with_body(web::not_found_page())))
             }
-
-            // fall back to server error response
-            futures::future::ok(hyper::Response::new()
-                .with_status(StatusCode::InternalServerError))
-        }))
+        }
     }
 }
 
 #[derive(Serialize)]
 enum ResponseType {
     #[serde(rename = "ephemeral")]
-    #[allow(dead_code)]
     Ephemeral,
     #[serde(rename = "in_channel")]
     InChannel
@@ -184,6 +633,16 @@ pub struct CommandResponse {
     payload: slack_hook::Payload
 }
 
+impl CommandResponse {
+    // Only shown to the user that sent the command
+    fn ephemeral(self) -> Self {
+        CommandResponse {
+            response_type: ResponseType::Ephemeral,
+            payload: self.payload
+        }
+    }
+}
+
 impl Into<CommandResponse> for slack_hook::Payload {
     fn into(self) -> CommandResponse {
         CommandResponse {
@@ -0,0 +1,150 @@
+use dotenv;
+use std::env;
+use std::fmt::Display;
+use std::mem;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+// Optional settings, read from the environment at runtime once dotenv has loaded .env.
+// Settings the bot cannot run without are still checked at compile time with dotenv!
+#[derive(Clone, Debug, Serialize)]
+pub struct Config {
+    pub webhook_urls: Vec<String>,
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<String>,
+    pub webhook_max_attempts: u32,
+    pub admin_user_ids: Vec<String>,
+    pub scoring_rules: Vec<String>,
+    pub streak_skip_weekdays: Vec<String>,
+    pub streak_skip_dates: Vec<String>,
+    pub streak_milestones: Vec<String>,
+    pub podium_points: Vec<String>,
+    pub penalty_points: i32,
+    pub penalty_daily_cap: i32,
+    pub penalty_thread_grace: bool,
+    pub season_length: String,
+    pub ratings_enabled: bool,
+    pub rating_k_factor: f64,
+    pub hot_half_life_hours: f64,
+    pub teams: Vec<String>,
+    pub team_usergroups: Vec<String>,
+    pub daily_post: bool,
+    pub chart_top_users: usize,
+    pub anticheat_max_attempts: i64,
+    pub anticheat_window_minutes: i64,
+    pub anticheat_disqualify: bool,
+    pub rate_limit_burst: u32,
+    pub rate_limit_per_minute: u32,
+    pub rate_limit_mute_secs: u64,
+    pub slack_api_url: String,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            webhook_urls: list("WEBHOOK_URLS"),
+            webhook_secret: var("WEBHOOK_SECRET"),
+            webhook_max_attempts: parse_or("WEBHOOK_MAX_ATTEMPTS", 5),
+            admin_user_ids: list("ADMIN_USER_IDS"),
+            scoring_rules: list("SCORING_RULES"),
+            streak_skip_weekdays: list("STREAK_SKIP_WEEKDAYS"),
+            streak_skip_dates: list("STREAK_SKIP_DATES"),
+            streak_milestones: list("STREAK_MILESTONES"),
+            podium_points: list("PODIUM_POINTS"),
+            penalty_points: parse_or("PENALTY_POINTS", 0),
+            penalty_daily_cap: parse_or("PENALTY_DAILY_CAP", 0),
+            penalty_thread_grace: parse_or("PENALTY_THREAD_GRACE", true),
+            season_length: var("SEASON_LENGTH").unwrap_or_else(|| String::from("manual")),
+            ratings_enabled: parse_or("RATINGS_ENABLED", false),
+            rating_k_factor: parse_or("RATING_K_FACTOR", 32.0),
+            hot_half_life_hours: parse_or("HOT_HALF_LIFE_HOURS", 72.0),
+            teams: list("TEAMS"),
+            team_usergroups: list("TEAM_USERGROUPS"),
+            daily_post: parse_or("DAILY_POST", false),
+            chart_top_users: parse_or("CHART_TOP_USERS", 5),
+            anticheat_max_attempts: parse_or("ANTICHEAT_MAX_ATTEMPTS", 0),
+            anticheat_window_minutes: parse_or("ANTICHEAT_WINDOW_MINUTES", 5),
+            anticheat_disqualify: parse_or("ANTICHEAT_DISQUALIFY", false),
+            rate_limit_burst: parse_or("RATE_LIMIT_BURST", 0),
+            rate_limit_per_minute: parse_or("RATE_LIMIT_PER_MINUTE", 10),
+            rate_limit_mute_secs: parse_or("RATE_LIMIT_MUTE_SECS", 0),
+            slack_api_url: var("SLACK_API_URL").unwrap_or_else(|| String::from("https://slack.com/api")),
+        }
+    }
+
+    // Re-reads .env over the top of whatever was loaded at startup
+    pub fn reload() -> Self {
+        match dotenv::dotenv_iter() {
+            Ok(items) => {
+                for item in items {
+                    match item {
+                        Ok((key, value)) => env::set_var(key, value),
+                        Err(e) => warn!("Could not read .env entry: {}", e)
+                    }
+                }
+            },
+            Err(e) => warn!("Could not re-read .env, using current environment: {}", e)
+        }
+
+        Self::from_env()
+    }
+
+    pub fn is_admin(&self, user_id: &str) -> bool {
+        self.admin_user_ids.iter().any(|id| id == user_id)
+    }
+}
+
+// Config that can be reloaded while the bot is running. Readers take a copy so the lock is never held for long
+#[derive(Clone)]
+pub struct SharedConfig {
+    config: Arc<RwLock<Config>>
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig {
+            config: Arc::new(RwLock::new(config))
+        }
+    }
+
+    pub fn get(&self) -> Config {
+        match self.config.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone()
+        }
+    }
+
+    // Returns the config that was replaced
+    pub fn replace(&self, config: Config) -> Config {
+        match self.config.write() {
+            Ok(mut current) => mem::replace(&mut *current, config),
+            Err(poisoned) => mem::replace(&mut *poisoned.into_inner(), config)
+        }
+    }
+}
+
+// Blank entries in .env are treated the same as missing ones
+fn var(key: &str) -> Option<String> {
+    env::var(key).ok()
+        .map(|value| String::from(value.trim()))
+        .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+fn list(key: &str) -> Vec<String> {
+    var(key)
+        .map(|value| value.split(',')
+            .map(|item| String::from(item.trim()))
+            .filter(|item| !item.is_empty())
+            .collect())
+        .unwrap_or_default()
+}
+
+fn parse_or<T>(key: &str, default: T) -> T where T: FromStr, T::Err: Display {
+    match var(key) {
+        Some(value) => value.parse().unwrap_or_else(|e| {
+            warn!("Invalid value '{}' for {}, using default: {}", value, key, e);
+            default
+        }),
+        None => default
+    }
+}
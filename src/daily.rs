@@ -0,0 +1,55 @@
+use chrono::NaiveDate;
+use config::Config;
+use diesel::PgConnection;
+use runner::DayCycle;
+use scoreboard::Penalties;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use storage;
+use teams;
+use types::Error;
+
+// A summary of the day that just ended, posted to the meta channel as the new day starts
+pub fn build_post(conn: &PgConnection, config: &Config, day: NaiveDate) -> Result<String, Error> {
+    // Two days back is always enough to cover the whole of yesterday, fuzz and all
+    let since = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    let dengs = storage::load_since(conn, Some(since))?
+        .into_iter()
+        .filter(|deng| DayCycle::day_of(deng.ts) == day)
+        .collect::<Vec<_>>();
+
+    let dengers = dengs.iter()
+        .filter(|deng| deng.successful)
+        .map(|deng| deng.user_id.as_str())
+        .collect::<HashSet<_>>();
+    let first = dengs.iter()
+        .filter(|deng| deng.successful)
+        .min_by_key(|deng| deng.ts)
+        .map(|deng| deng.user_id.clone());
+
+    let mut post = format!("*Deng report for {}*\n", day.format("%A %e %B"));
+    post.push_str(&match first {
+        Some(user_id) => format!("{} denged, and <@{}> was first :first_place_medal:", plural(dengers.len()), user_id),
+        None => String::from("Nobody denged :cry:")
+    });
+
+    let members = storage::load_team_members(conn)?;
+    if !members.is_empty() {
+        let penalties = Penalties::from_config(config);
+        let adjustments = storage::load_adjustments(conn, Some(since))?
+            .into_iter()
+            .filter(|adjustment| DayCycle::day_of(adjustment.ts) == day)
+            .collect::<Vec<_>>();
+        post.push_str("\n\n");
+        post.push_str(&teams::format_standings("Team scores", &teams::tally(&dengs, &adjustments, &penalties, &members)));
+    }
+
+    Ok(post)
+}
+
+fn plural(count: usize) -> String {
+    match count {
+        1 => String::from("1 person"),
+        _ => format!("{} people", count)
+    }
+}
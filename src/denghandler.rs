@@ -2,13 +2,28 @@ extern crate regex;
 
 use slack;
 use slack::*;
-use types::{Broadcast, SlackInfo};
+use catchup;
+use config::SharedConfig;
+use metrics;
+use replay::Attempt;
+use runner::DayCycle;
+use types::{self, Broadcast, MessageId, SlackInfo};
+use std::collections::{HashSet, VecDeque};
 use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+
+// How many recent messages to remember, to catch Slack redelivering them after a reconnect
+const RECENT_CAPACITY: usize = 1000;
 
 pub struct DengHandler {
     tx: Sender<Broadcast>,
     info_tx: Sender<SlackInfo>,
-    info: Option<SlackInfo>
+    info: Option<SlackInfo>,
+    config: SharedConfig,
+    recent: VecDeque<MessageId>,
+    seen: HashSet<MessageId>,
+    // Slack timestamp of the latest message handled from the listen channel, to catch up from after a reconnect
+    last_ts: Option<String>
 }
 
 impl EventHandler for DengHandler {
@@ -37,35 +52,254 @@ impl EventHandler for DengHandler {
         if let Err(e) = self.info_tx.send(info) {
             debug!("Could not broadcast on connect Slack info message: {}", e)
         }
+
+        self.catch_up();
     }
 }
 
 impl DengHandler {
-    pub fn new(tx: Sender<Broadcast>, info_tx: Sender<SlackInfo>) -> Self {
-        DengHandler { tx, info_tx, info: None }
+    pub fn new(tx: Sender<Broadcast>, info_tx: Sender<SlackInfo>, config: SharedConfig, last_ts: Option<String>) -> Self {
+        DengHandler {
+            tx,
+            info_tx,
+            info: None,
+            config,
+            recent: VecDeque::with_capacity(RECENT_CAPACITY),
+            seen: HashSet::with_capacity(RECENT_CAPACITY),
+            last_ts
+        }
+    }
+
+    // Replays anything posted to the listen channel while we were disconnected, in order and through the
+    // same path as live messages, so duplicates are dropped the same way
+    fn catch_up(&mut self) {
+        let listen_channel_id = match self.info {
+            Some(ref info) => info.listen_channel_id.clone(),
+            None => return
+        };
+        let last_ts = match self.last_ts.clone() {
+            Some(last_ts) => last_ts,
+            None => {
+                info!("No messages have been stored yet, so there is nothing to catch up on");
+                return;
+            }
+        };
+
+        // Anything from before the current day would be scored as today's if handled live, so it's replayed into its own day
+        let day_start = format!("{}.000000", DayCycle::current_start().timestamp());
+
+        let config = self.config.get();
+        match catchup::history(&config.slack_api_url, dotenv!("SLACK_API_KEY"), &listen_channel_id, &last_ts) {
+            Ok(messages) => {
+                let (earlier, today): (Vec<_>, Vec<_>) = messages.into_iter()
+                    .partition(|message| message.ts.as_ref().map_or(false, |ts| *ts < day_start));
+                info!("Catching up on {} missed messages since {}, {} of them from earlier days",
+                      earlier.len() + today.len(), last_ts, earlier.len());
+
+                let mut attempts = vec![];
+                for message in earlier {
+                    match self.to_broadcast(message) {
+                        Ok(broadcast) => attempts.extend(broadcast.and_then(to_attempt)),
+                        Err(e) => error!("Could not process missed message: {}", e)
+                    }
+                }
+                if !attempts.is_empty() {
+                    if let Err(e) = self.tx.send(Broadcast::Missed { attempts }) {
+                        error!("Could not send missed attempts: {}", e);
+                    }
+                }
+
+                for message in today {
+                    if let Err(e) = self.handle_message(message) {
+                        error!("Could not process missed message: {}", e);
+                    }
+                }
+            },
+            Err(e) => error!("Could not catch up on missed messages: {}", e)
+        }
+    }
+
+    // Returns false if the message has been seen recently
+    fn remember(&mut self, message_id: &MessageId) -> bool {
+        if !self.seen.insert(message_id.clone()) {
+            return false;
+        }
+
+        self.recent.push_back(message_id.clone());
+        if self.recent.len() > RECENT_CAPACITY {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
     }
 
     pub fn handle_message(&mut self, message: slack::api::MessageStandard) -> Result<(), String> {
+        match self.to_broadcast(message)? {
+            Some(msg) => self.tx.send(msg).map_err(|e| format!("{}", e)),
+            None => Ok(())
+        }
+    }
+
+    // None for messages outside the listen channel, and for ones that have been seen recently
+    fn to_broadcast(&mut self, message: slack::api::MessageStandard) -> Result<Option<Broadcast>, String> {
+        // Replies in threads carry the timestamp of their parent message
+        let thread_reply = match (&message.thread_ts, &message.ts) {
+            (&Some(ref thread_ts), &Some(ref ts)) => thread_ts != ts,
+            (&Some(_), &None) => true,
+            _ => false
+        };
+
         let text = message.text.ok_or_else(|| String::from("No text in message"))?;
         let user = message.user.ok_or_else(|| String::from("No user in message"))?;
 
         debug!("Message from {}: {}", user, text);
 
         if let Some(channel_id) = message.channel {
-            let listen_channel_id = &self.info.as_ref()
+            let in_listen_channel = channel_id == self.info.as_ref()
                 .ok_or_else(|| String::from("Info has not been initialised yet"))?
                 .listen_channel_id;
 
-            if channel_id == *listen_channel_id {
+            if in_listen_channel {
+                let ts = message.ts.as_ref()
+                    .and_then(|ts| types::parse_slack_ts(ts))
+                    .unwrap_or_else(SystemTime::now);
+                let message_id = message.ts.map(|ts| MessageId { channel_id, ts });
+
+                if let Some(ref message_id) = message_id {
+                    if !self.remember(message_id) {
+                        info!("Dropping duplicate message {:?}", message_id);
+                        metrics::increment(&metrics::DUPLICATES_DROPPED);
+                        return Ok(None);
+                    }
+                    if self.last_ts.as_ref().map_or(true, |last_ts| message_id.ts > *last_ts) {
+                        self.last_ts = Some(message_id.ts.clone());
+                    }
+                }
+
                 let msg = match text.as_str() {
-                    "deng" => Broadcast::Deng(user),
-                    _ => Broadcast::NonDeng(user)
+                    "deng" => Broadcast::Deng { user_id: user, ts, message_id },
+                    _ => Broadcast::NonDeng { user_id: user, ts, thread_reply, message_id }
                 };
 
-                self.tx.send(msg).map_err(|e| format!("{}", e))?;
+                return Ok(Some(msg));
             }
         }
 
-        Ok(())
+        Ok(None)
+    }
+}
+
+fn to_attempt(broadcast: Broadcast) -> Option<Attempt> {
+    match broadcast {
+        Broadcast::Deng { user_id, ts, message_id } => {
+            Some(Attempt { user_id, ts, successful: true, thread_reply: false, message_id, deng_id: None })
+        },
+        Broadcast::NonDeng { user_id, ts, thread_reply, message_id } => {
+            Some(Attempt { user_id, ts, successful: false, thread_reply, message_id, deng_id: None })
+        },
+        Broadcast::Missed { .. } => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use catchup::tests::serve;
+    use config::Config;
+    use serde_json;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn handler(api_url: &str, last_ts: Option<String>) -> (DengHandler, Receiver<Broadcast>) {
+        let (tx, rx) = mpsc::channel();
+        let (info_tx, _info_rx) = mpsc::channel();
+        let mut config = Config::from_env();
+        config.slack_api_url = String::from(api_url);
+
+        let mut handler = DengHandler::new(tx, info_tx, SharedConfig::new(config), last_ts);
+        handler.info = Some(SlackInfo {
+            users: vec![],
+            listen_channel_id: String::from("C1"),
+            meta_channel_id: String::from("C2"),
+        });
+        (handler, rx)
+    }
+
+    fn message(user: &str, text: &str, ts: &str) -> slack::api::MessageStandard {
+        let json = format!(r#"{{"type":"message","channel":"C1","user":"{}","text":"{}","ts":"{}"}}"#, user, text, ts);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn drops_redelivered_messages() {
+        let (mut handler, broadcasts) = handler("http://127.0.0.1:1", None);
+
+        handler.handle_message(message("U1", "deng", "1533000001.000100")).unwrap();
+        handler.handle_message(message("U1", "deng", "1533000001.000100")).unwrap();
+
+        assert_eq!(broadcasts.try_iter().count(), 1);
+        assert_eq!(handler.last_ts, Some(String::from("1533000001.000100")));
+    }
+
+    #[test]
+    fn broadcasts_when_slack_says_the_message_was_sent() {
+        let (mut handler, broadcasts) = handler("http://127.0.0.1:1", None);
+
+        handler.handle_message(message("U1", "deng", "1533000001.000100")).unwrap();
+
+        match broadcasts.try_recv().unwrap() {
+            Broadcast::Deng { ts, .. } => assert_eq!(ts, UNIX_EPOCH + Duration::new(1_533_000_001, 100_000)),
+            _ => panic!("Expected a deng")
+        }
+    }
+
+    #[test]
+    fn catches_up_on_missed_messages_once() {
+        let (url, _requests) = serve(vec![r#"{"ok":true,"has_more":false,"messages":[
+            {"type":"message","user":"U2","text":"deng","ts":"9999999903.000300"},
+            {"type":"message","user":"U1","text":"nearly","ts":"9999999902.000200"},
+            {"type":"message","user":"U1","text":"deng","ts":"9999999901.000100"}
+        ]}"#]);
+        let (mut handler, broadcasts) = handler(&url, Some(String::from("9999999900.000000")));
+
+        // Slack redelivered the first message before catching up began
+        handler.handle_message(message("U1", "deng", "9999999901.000100")).unwrap();
+        handler.catch_up();
+
+        let users = broadcasts.try_iter()
+            .map(|broadcast| match broadcast {
+                Broadcast::Deng { user_id, .. } => format!("{} deng", user_id),
+                Broadcast::NonDeng { user_id, .. } => format!("{} other", user_id),
+                Broadcast::Missed { attempts } => format!("{} missed", attempts.len()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(users, vec!["U1 deng", "U1 other", "U2 deng"]);
+        assert_eq!(handler.last_ts, Some(String::from("9999999903.000300")));
+    }
+
+    #[test]
+    fn replays_messages_from_earlier_days_separately() {
+        let (url, _requests) = serve(vec![r#"{"ok":true,"has_more":false,"messages":[
+            {"type":"message","user":"U2","text":"deng","ts":"9999999903.000300"},
+            {"type":"message","user":"U1","text":"nearly","ts":"1533000002.000200"},
+            {"type":"message","user":"U1","text":"deng","ts":"1533000001.000100"}
+        ]}"#]);
+        let (mut handler, broadcasts) = handler(&url, Some(String::from("1533000000.000000")));
+
+        handler.catch_up();
+
+        match broadcasts.try_recv().unwrap() {
+            Broadcast::Missed { attempts } => {
+                let attempts = attempts.iter().map(|attempt| (attempt.user_id.as_str(), attempt.successful)).collect::<Vec<_>>();
+                assert_eq!(attempts, vec![("U1", true), ("U1", false)]);
+            },
+            _ => panic!("Expected the earlier attempts first")
+        }
+        match broadcasts.try_recv().unwrap() {
+            Broadcast::Deng { user_id, .. } => assert_eq!(user_id, "U2"),
+            _ => panic!("Expected today's deng")
+        }
+        assert!(broadcasts.try_recv().is_err());
     }
 }
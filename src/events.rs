@@ -0,0 +1,203 @@
+use futures::sync::mpsc::{self, Sender};
+use hyper::{self, Body, Chunk};
+use runner::DayCycle;
+use serde_json;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use types::Deng;
+
+// How many events a client can fall behind by before we give up on it
+const CLIENT_BUFFER: usize = 64;
+const KEEPALIVE_INTERVAL_SECS: u64 = 15;
+
+type ClientSender = Sender<Result<Chunk, hyper::Error>>;
+
+struct Client {
+    id: usize,
+    tx: ClientSender,
+    // Live events held back while the client's missed events are loaded, so everything arrives in order
+    pending: Option<Vec<(i32, String)>>,
+    // Highest event ID sent, so nothing is sent twice
+    last_event_id: i32,
+}
+
+impl Client {
+    fn try_send(&mut self, message: &str, event_id: Option<i32>) -> bool {
+        if event_id.map_or(false, |id| id <= self.last_event_id) {
+            return true;
+        }
+        if self.tx.try_send(Ok(Chunk::from(String::from(message)))).is_err() {
+            return false;
+        }
+        if let Some(id) = event_id {
+            self.last_event_id = id;
+        }
+        true
+    }
+
+    // Returns false once the client has closed, or fallen too far behind while its missed events were loaded
+    fn deliver(&mut self, message: &str, event_id: Option<i32>) -> bool {
+        if let Some(ref mut pending) = self.pending {
+            // Keepalives aren't worth holding on to
+            if let Some(id) = event_id {
+                pending.push((id, String::from(message)));
+            }
+            return pending.len() <= CLIENT_BUFFER;
+        }
+        self.try_send(message, event_id)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Deng,
+    NonDeng
+}
+
+#[derive(Serialize)]
+pub struct DengEvent {
+    #[serde(skip)]
+    id: i32,
+    user_id: String,
+    outcome: Outcome,
+    points: i32,
+    timestamp: String,
+}
+
+impl DengEvent {
+    pub fn from_deng(deng: &Deng) -> Self {
+        DengEvent {
+            id: deng.id,
+            user_id: deng.user_id.clone(),
+            outcome: if deng.successful { Outcome::Deng } else { Outcome::NonDeng },
+            points: deng.value(),
+            timestamp: DayCycle::local(deng.ts).to_rfc3339(),
+        }
+    }
+
+    // The deng's row ID doubles as the event ID so clients can resume with Last-Event-ID
+    fn to_message(&self) -> Result<String, serde_json::Error> {
+        Ok(format!("id: {}\nevent: deng\ndata: {}\n\n", self.id, serde_json::to_string(self)?))
+    }
+}
+
+// Fans processed dengs out to every connected Server-Sent Events client.
+// Sends never block: a client that can't keep up is dropped and will reconnect with Last-Event-ID.
+#[derive(Clone)]
+pub struct EventStream {
+    clients: Arc<Mutex<Vec<Client>>>,
+    next_client_id: Arc<AtomicUsize>
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        let stream = EventStream {
+            clients: Arc::new(Mutex::new(vec![])),
+            next_client_id: Arc::new(AtomicUsize::new(0))
+        };
+
+        let keepalive = stream.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(KEEPALIVE_INTERVAL_SECS));
+                keepalive.send(": keepalive\n\n", None);
+            }
+        });
+
+        stream
+    }
+
+    pub fn publish(&self, event: &DengEvent) {
+        match event.to_message() {
+            Ok(message) => self.send(&message, Some(event.id)),
+            Err(e) => error!("Could not serialise deng event: {}", e)
+        }
+    }
+
+    // Opens a new stream. A client resuming after a disconnect is registered before its missed events are
+    // loaded, so nothing published in between is lost, but gets no live events until `replay` is called.
+    // Returns the client's ID for that
+    pub fn subscribe(&self, resuming: bool, replay_capacity: usize) -> (usize, Body) {
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER + replay_capacity);
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+
+        match self.clients.lock() {
+            Ok(mut clients) => {
+                clients.push(Client { id, tx, pending: if resuming { Some(vec![]) } else { None }, last_event_id: 0 });
+                info!("Event stream client connected. {} client(s) listening", clients.len());
+            },
+            Err(e) => error!("Could not register event stream client: {}", e)
+        }
+
+        (id, Body::from(rx))
+    }
+
+    // Sends a resuming client the events it missed, then anything published since it subscribed.
+    // If there were more missed events than could be loaded, the client is told so and the stream is closed,
+    // so it reconnects with the last event ID it got and picks up from there
+    pub fn replay(&self, client_id: usize, missed: &[DengEvent], truncated: bool) {
+        let mut clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(e) => {
+                error!("Could not lock event stream clients: {}", e);
+                return;
+            }
+        };
+
+        let position = match clients.iter().position(|client| client.id == client_id) {
+            Some(position) => position,
+            None => return
+        };
+
+        let connected = {
+            let client = &mut clients[position];
+            let pending = client.pending.take().unwrap_or_default();
+
+            let mut connected = missed.iter().all(|event| match event.to_message() {
+                Ok(message) => client.try_send(&message, Some(event.id)),
+                Err(e) => {
+                    error!("Could not serialise deng event: {}", e);
+                    true
+                }
+            });
+
+            if connected && truncated {
+                let notice = format!("event: truncated\ndata: {{\"replayed\":{}}}\n\n", missed.len());
+                client.try_send(&notice, None);
+                info!("Closing event stream client after replaying {} of its missed events", missed.len());
+                connected = false;
+            }
+
+            connected && pending.iter().all(|&(id, ref message)| client.try_send(message, Some(id)))
+        };
+
+        if !connected {
+            clients.remove(position);
+        }
+    }
+
+    fn send(&self, message: &str, event_id: Option<i32>) {
+        match self.clients.lock() {
+            Ok(mut clients) => {
+                let before = clients.len();
+                let mut connected = Vec::with_capacity(before);
+
+                for mut client in clients.drain(..) {
+                    if client.deliver(message, event_id) {
+                        connected.push(client);
+                    }
+                }
+
+                if connected.len() < before {
+                    info!("Dropped {} closed or lagging event stream client(s)", before - connected.len());
+                }
+
+                *clients = connected;
+            },
+            Err(e) => error!("Could not lock event stream clients: {}", e)
+        }
+    }
+}
@@ -14,16 +14,47 @@ extern crate r2d2_diesel;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
+extern crate reqwest;
 
 mod denghandler;
 mod storage;
 mod types;
 mod runner;
 mod command;
+mod scoreboard;
+mod web;
+mod events;
+mod config;
+mod webhooks;
+mod subcommand;
+mod admin;
+mod announce;
+mod scoring;
+mod streaks;
+mod seasons;
+mod achievements;
+mod ratings;
+mod teams;
+mod daily;
+mod stats;
+mod chart;
+mod analytics;
+mod anticheat;
+mod metrics;
+mod ratelimit;
+mod catchup;
+mod replay;
+mod backfill;
+mod recompute;
+mod backup;
+mod cli;
 
 use runner::*;
+use config::{Config, SharedConfig};
 use dotenv::dotenv;
+use std::env;
 use std::fs::File;
+use std::process;
 use simplelog::*;
 
 fn main() {
@@ -34,7 +65,14 @@ fn main() {
     let listen_port = dotenv!("LISTEN_PORT");
     let log_path = dotenv!("LOG_PATH");
 
-    init_logger(&log_path);
+    // Anything on the command line is a maintenance command rather than a request to run the bot
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    if args.is_empty() {
+        init_logger(&log_path);
+    } else {
+        init_cli_logger();
+    }
 
     info!("Starting up dengbot");
 
@@ -45,7 +83,18 @@ fn main() {
 
     info!("Connected to database");
 
-    let mut runner = Runner::new(db_conn_pool.clone());
+    let config = SharedConfig::new(Config::from_env());
+
+    if !args.is_empty() {
+        let conn = db_conn_pool.get().expect("Could not get connection to DB");
+        if let Err(e) = cli::run(&args, &conn, &config.get()) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut runner = Runner::new(db_conn_pool.clone(), config);
     loop {
         runner.start(&api_key, &listen_port);
     }
@@ -54,17 +103,24 @@ fn main() {
 fn init_logger(path: &str) {
     let mut loggers: Vec<Box<SharedLogger>> = vec!();
     match File::create(path) {
-        Ok(f) => loggers.push(WriteLogger::new(LevelFilter::Debug, Config::default(), f)),
+        Ok(f) => loggers.push(WriteLogger::new(LevelFilter::Debug, simplelog::Config::default(), f)),
         Err(e) => println!("Could not create log file at {}: {}", path, e)
     }
-    match TermLogger::new(LevelFilter::Debug, Config::default()) {
+    match TermLogger::new(LevelFilter::Debug, simplelog::Config::default()) {
         Some(logger) => loggers.push(logger),
         None => {
             println!("Could not create terminal logger: falling back to simple logger");
-            loggers.push(SimpleLogger::new(LevelFilter::Debug, Config::default()));
+            loggers.push(SimpleLogger::new(LevelFilter::Debug, simplelog::Config::default()));
         }
     }
     if let Err(e) = CombinedLogger::init(loggers) {
         println!("Could not initialise loggers: {}", e);
     }
-}
\ No newline at end of file
+}
+
+// Commands print their own output, so only warnings are logged, and the bot's log file is left alone
+fn init_cli_logger() {
+    if let Err(e) = SimpleLogger::init(LevelFilter::Warn, simplelog::Config::default()) {
+        println!("Could not initialise logger: {}", e);
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+// Counters since the bot started, served in the Prometheus text format from /metrics
+pub static DENGS_STORED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static FAILURES_STORED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static MESSAGES_THROTTLED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static USERS_MUTED: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static DUPLICATES_DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn counters() -> [(&'static str, &'static str, &'static AtomicUsize); 5] {
+    [
+        ("dengbot_dengs_stored_total", "Successful dengs stored", &DENGS_STORED),
+        ("dengbot_failures_stored_total", "Failed attempts stored", &FAILURES_STORED),
+        ("dengbot_messages_throttled_total", "Messages in the listen channel dropped by the rate limiter", &MESSAGES_THROTTLED),
+        ("dengbot_users_muted_total", "Times a user has been muted for spamming the listen channel", &USERS_MUTED),
+        ("dengbot_duplicates_dropped_total", "Messages Slack delivered more than once", &DUPLICATES_DROPPED),
+    ]
+}
+
+pub fn increment(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn render() -> String {
+    counters().iter()
+        .map(|&(name, help, counter)| {
+            format!("# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, counter.load(Ordering::Relaxed))
+        })
+        .collect()
+}
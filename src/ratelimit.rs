@@ -0,0 +1,164 @@
+use config::Config;
+use metrics;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    // Messages a user can send in a burst before being throttled
+    burst: f64,
+    per_minute: f64,
+    // How long to ignore a user for once they've run out, or zero to never mute
+    mute: Duration,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Self {
+        Limits {
+            burst: config.rate_limit_burst as f64,
+            per_minute: config.rate_limit_per_minute as f64,
+            mute: Duration::from_secs(config.rate_limit_mute_secs),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.burst > 0.0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allow,
+    // Out of tokens. Failures are still stored, so they count towards penalties and anti-cheat, but nobody is told
+    Throttle,
+    // Ignore this user's dengs for now. Their failures are treated as throttled
+    Muted,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // Messages throttled since the user was last allowed through, logged as one line rather than hundreds
+    coalesced: u32,
+}
+
+// A token bucket for each user in the listen channel
+pub struct RateLimiter {
+    buckets: HashMap<String, Bucket>,
+    muted_until: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter {
+            buckets: HashMap::new(),
+            muted_until: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, user_id: &str, limits: &Limits) -> Decision {
+        if !limits.enabled() {
+            return Decision::Allow;
+        }
+
+        let now = Instant::now();
+
+        if let Some(&until) = self.muted_until.get(user_id) {
+            if now < until {
+                metrics::increment(&metrics::MESSAGES_THROTTLED);
+                return Decision::Muted;
+            }
+            info!("Unmuting {}", user_id);
+        }
+        self.muted_until.remove(user_id);
+
+        let bucket = self.buckets.entry(String::from(user_id))
+            .or_insert_with(|| Bucket { tokens: limits.burst, updated: now, coalesced: 0 });
+
+        let elapsed = now.duration_since(bucket.updated);
+        let elapsed_minutes = (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9) / 60.0;
+        bucket.tokens = (bucket.tokens + elapsed_minutes * limits.per_minute).min(limits.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            if bucket.coalesced > 0 {
+                info!("Throttled {} messages from {}", bucket.coalesced, user_id);
+                bucket.coalesced = 0;
+            }
+            return Decision::Allow;
+        }
+
+        bucket.coalesced += 1;
+        metrics::increment(&metrics::MESSAGES_THROTTLED);
+
+        if limits.mute > Duration::from_secs(0) {
+            warn!("Muting {} for {} seconds for spamming the listen channel", user_id, limits.mute.as_secs());
+            metrics::increment(&metrics::USERS_MUTED);
+            self.muted_until.insert(String::from(user_id), now + limits.mute);
+            return Decision::Muted;
+        }
+
+        Decision::Throttle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(burst: f64, per_minute: f64, mute_secs: u64) -> Limits {
+        Limits { burst, per_minute, mute: Duration::from_secs(mute_secs) }
+    }
+
+    fn decisions(limiter: &mut RateLimiter, limits: &Limits, count: usize) -> Vec<Decision> {
+        (0..count).map(|_| limiter.check("U1", limits)).collect()
+    }
+
+    // Pretends the user's last message was this long ago
+    fn rewind(limiter: &mut RateLimiter, secs: u64) {
+        let bucket = limiter.buckets.get_mut("U1").unwrap();
+        bucket.updated = bucket.updated - Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn allows_everything_when_off() {
+        let mut limiter = RateLimiter::new();
+
+        assert!(decisions(&mut limiter, &limits(0.0, 0.0, 0), 50).iter().all(|decision| *decision == Decision::Allow));
+    }
+
+    #[test]
+    fn throttles_after_the_burst() {
+        let mut limiter = RateLimiter::new();
+
+        assert_eq!(decisions(&mut limiter, &limits(3.0, 0.0, 0), 4),
+                   vec![Decision::Allow, Decision::Allow, Decision::Allow, Decision::Throttle]);
+    }
+
+    #[test]
+    fn refills_up_to_the_burst() {
+        let mut limiter = RateLimiter::new();
+        let limits = limits(2.0, 10.0, 0);
+        decisions(&mut limiter, &limits, 2);
+
+        // Half a minute earns five tokens, but the bucket only holds two
+        rewind(&mut limiter, 30);
+
+        assert_eq!(decisions(&mut limiter, &limits, 3), vec![Decision::Allow, Decision::Allow, Decision::Throttle]);
+    }
+
+    #[test]
+    fn mutes_until_the_mute_expires() {
+        let mut limiter = RateLimiter::new();
+        let limits = limits(1.0, 60.0, 60);
+
+        assert_eq!(decisions(&mut limiter, &limits, 3), vec![Decision::Allow, Decision::Muted, Decision::Muted]);
+
+        limiter.muted_until.insert(String::from("U1"), Instant::now() - Duration::from_secs(1));
+        rewind(&mut limiter, 10);
+
+        assert_eq!(limiter.check("U1", &limits), Decision::Allow);
+        assert!(!limiter.muted_until.contains_key("U1"));
+    }
+}
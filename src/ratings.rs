@@ -0,0 +1,201 @@
+use chrono::NaiveDate;
+use config::Config;
+use diesel::PgConnection;
+use runner::{DayCycle, Rollovers};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use storage::{self, NewRating, Source};
+use types::{Deng, Error, Rating};
+
+const INITIAL_RATING: f64 = 1500.0;
+
+// Each day is treated as a race between everyone who denged, finishing in their stored placement order.
+// Every racer is compared against every other, as if they had played a game of chess against each of them
+fn race_days<'a>(dengs: &'a [Deng], voided: &HashSet<i32>, rollovers: &Rollovers) -> BTreeMap<NaiveDate, Vec<&'a str>> {
+    // Only each user's first successful deng of the day is placed
+    let mut placed = dengs.iter()
+        .filter(|deng| deng.successful && !voided.contains(&deng.id))
+        .filter_map(|deng| deng.placement.map(|placement| (rollovers.day_of(deng.ts), placement, deng)))
+        .collect::<Vec<_>>();
+    placed.sort_by_key(|&(day, placement, deng)| (day, placement, deng.ts));
+
+    placed.into_iter()
+        .fold(BTreeMap::new(), |mut days, (day, _, deng)| {
+            let racers = days.entry(day).or_insert_with(Vec::new);
+            if !racers.contains(&deng.user_id.as_str()) {
+                racers.push(deng.user_id.as_str());
+            }
+            days
+        })
+}
+
+fn expected(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+// Days with only one racer don't change anyone's rating
+fn rate_race(ratings: &mut HashMap<String, f64>, day: NaiveDate, racers: &[&str], k_factor: f64) -> Vec<NewRating> {
+    if racers.len() < 2 {
+        return vec![];
+    }
+
+    let before = racers.iter()
+        .map(|user_id| ratings.get(*user_id).cloned().unwrap_or(INITIAL_RATING))
+        .collect::<Vec<_>>();
+    let k = k_factor / (racers.len() - 1) as f64;
+
+    racers.iter()
+        .enumerate()
+        .map(|(place, user_id)| {
+            let change = before.iter()
+                .enumerate()
+                .filter(|&(other, _)| other != place)
+                .map(|(other, &opponent)| {
+                    let actual = if place < other { 1.0 } else { 0.0 };
+                    k * (actual - expected(before[place], opponent))
+                })
+                .sum::<f64>();
+
+            let rating = before[place] + change;
+            ratings.insert(String::from(*user_id), rating);
+
+            NewRating {
+                user_id: String::from(*user_id),
+                day,
+                rating,
+                change,
+                placement: place as i32 + 1,
+                racers: racers.len() as i32,
+            }
+        })
+        .collect()
+}
+
+fn calculate(conn: &PgConnection,
+             config: &Config,
+             ratings: &mut HashMap<String, f64>,
+             after: Option<NaiveDate>) -> Result<Vec<NewRating>, Error> {
+
+    let dengs = storage::load(conn)?;
+    let voided = storage::load_voided_ids(conn)?;
+    let rollovers = Rollovers::load(conn)?;
+
+    // Today's race is still being run
+    let today = DayCycle::today();
+
+    let mut rated = vec![];
+    for (day, racers) in race_days(&dengs, &voided, &rollovers) {
+        if after.map_or(true, |after| day > after) && day < today {
+            rated.extend(rate_race(ratings, day, &racers, config.rating_k_factor));
+        }
+    }
+
+    Ok(rated)
+}
+
+// Rates every finished day since the last one rated. Called as each new day starts
+pub fn update(conn: &PgConnection, config: &Config) -> Result<usize, Error> {
+    if !config.ratings_enabled {
+        return Ok(0);
+    }
+
+    let mut ratings = current(&storage::load_ratings(conn)?);
+    let rated = calculate(conn, config, &mut ratings, storage::last_rated_day(conn)?)?;
+
+    storage::store_ratings(conn, &rated)?;
+    Ok(rated.len())
+}
+
+// Throws away the rating history and rates every day again from scratch
+pub fn recompute(conn: &PgConnection, actor: &str, config: &Config, source: Source) -> Result<usize, Error> {
+    let rated = calculate(conn, config, &mut HashMap::new(), None)?;
+
+    storage::replace_ratings(conn, actor, &rated, source)?;
+    Ok(rated.len())
+}
+
+// Each user's latest rating
+fn current(history: &[Rating]) -> HashMap<String, f64> {
+    history.iter()
+        .map(|rating| (rating.user_id.clone(), rating.rating))
+        .collect()
+}
+
+pub fn format_ratings(history: &[Rating]) -> String {
+    let mut latest = history.iter()
+        .fold(HashMap::new(), |mut map: HashMap<&str, &Rating>, rating| {
+            map.insert(rating.user_id.as_str(), rating);
+            map
+        })
+        .into_iter()
+        .map(|(_, rating)| rating)
+        .collect::<Vec<_>>();
+
+    latest.sort_by(|first, second| second.rating.partial_cmp(&first.rating).unwrap_or(::std::cmp::Ordering::Equal));
+
+    match latest.len() {
+        0 => String::from("Nobody has a rating yet. Ratings are updated at the end of each day."),
+        _ => {
+            let lines = latest.into_iter()
+                .enumerate()
+                .map(|(rank, rating)| {
+                    format!("{}. <@{}> *{:.0}* ({:+.0} on {})", rank + 1, rating.user_id, rating.rating,
+                            rating.change, rating.day.format("%e %b"))
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("*Deng Ratings*\n{}", lines)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn deng(id: i32, user_id: &str, secs: u64, placement: Option<i32>) -> Deng {
+        Deng {
+            id,
+            ts: UNIX_EPOCH + Duration::from_secs(secs),
+            user_id: String::from(user_id),
+            successful: true,
+            days_first_deng: placement == Some(1),
+            users_first_deng: false,
+            points: 1,
+            thread_reply: false,
+            placement,
+            channel_id: None,
+            slack_ts: None,
+        }
+    }
+
+    #[test]
+    fn races_follow_stored_placement() {
+        // 1am UTC is the 9am Perth rollover. The late rollover put alice's 01:05 deng on the previous day
+        let day = 1_530_406_800;
+        let dengs = vec![
+            deng(1, "alice", day + 300, Some(2)),
+            deng(2, "bob", day + 1200, Some(1)),
+            deng(3, "carol", day + 1500, Some(2)),
+            deng(4, "bob", day + 1800, None),
+        ];
+        let mut rollovers = Rollovers::default();
+        let today = DayCycle::day_of(UNIX_EPOCH + Duration::from_secs(day));
+        rollovers.record(today, UNIX_EPOCH + Duration::from_secs(day + 900));
+
+        let days = race_days(&dengs, &HashSet::new(), &rollovers);
+        assert_eq!(days[&today.pred()], vec!["alice"]);
+        assert_eq!(days[&today], vec!["bob", "carol"]);
+    }
+
+    #[test]
+    fn voided_dengs_do_not_race() {
+        let dengs = vec![deng(1, "alice", 1_530_406_800, Some(1)), deng(2, "bob", 1_530_407_000, Some(2))];
+        let voided = vec![1].into_iter().collect();
+
+        let racers = race_days(&dengs, &voided, &Rollovers::default()).into_iter()
+            .flat_map(|(_, racers)| racers)
+            .collect::<Vec<_>>();
+        assert_eq!(racers, vec!["bob"]);
+    }
+}
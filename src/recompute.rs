@@ -0,0 +1,89 @@
+use config::Config;
+use diesel::PgConnection;
+use replay::{Attempt, Replay, Replayed};
+use runner::Rollovers;
+use storage::{self, Source};
+use types::{Deng, Error};
+
+// A stored deng that the current day cycle and scoring rules would have stored differently
+struct Change {
+    deng: Deng,
+    replayed: Replayed,
+}
+
+impl Change {
+    fn differences(&self) -> Vec<String> {
+        let (before, after) = (&self.deng, &self.replayed.deng);
+        let mut differences = vec![];
+
+        if before.days_first_deng != after.days_first_deng {
+            differences.push(format!("days_first_deng {} -> {}", before.days_first_deng, after.days_first_deng));
+        }
+        if before.users_first_deng != after.users_first_deng {
+            differences.push(format!("users_first_deng {} -> {}", before.users_first_deng, after.users_first_deng));
+        }
+        if before.placement != after.placement {
+            differences.push(format!("placement {} -> {}", format_placement(before.placement), format_placement(after.placement)));
+        }
+        if before.points != after.points {
+            differences.push(format!("points {} -> {}", before.points, after.points));
+        }
+
+        differences
+    }
+}
+
+fn format_placement(placement: Option<i32>) -> String {
+    placement.map_or_else(|| String::from("none"), |placement| placement.to_string())
+}
+
+// Replays every stored attempt through the current day cycle and scoring rules and reports any deng
+// that comes out differently. With `write`, those dengs are rewritten in a single transaction.
+// Days without a recorded rollover may have rolled over up to 15 minutes after 9am, so their dengs are
+// reported separately and never rewritten
+pub fn run(conn: &PgConnection, config: &Config, write: bool) -> Result<(), Error> {
+    let dengs = storage::load_in_order(conn)?;
+    let total = dengs.len();
+
+    let rollovers = Rollovers::load(conn)?;
+    let mut replay = Replay::new(config, rollovers.clone(), storage::load_voided_ids(conn)?);
+    let (changes, unrecorded): (Vec<_>, Vec<_>) = dengs.into_iter()
+        .map(|deng| {
+            let replayed = replay.attempt(Attempt::from_deng(&deng));
+            Change { deng, replayed }
+        })
+        .filter(|change| !change.differences().is_empty())
+        .partition(|change| rollovers.is_recorded(rollovers.day_of(change.deng.ts)));
+
+    if changes.is_empty() && unrecorded.is_empty() {
+        println!("All {} stored attempts already match the current rules", total);
+        return Ok(());
+    }
+
+    for change in &changes {
+        println!("#{} {} on {}: {}", change.deng.id, change.deng.user_id, rollovers.day_of(change.deng.ts),
+                 change.differences().join(", "));
+    }
+
+    if !unrecorded.is_empty() {
+        println!("{} attempts differ on days without a recorded rollover, and won't be rewritten:", unrecorded.len());
+        for change in &unrecorded {
+            println!("#{} {} on {}: {}", change.deng.id, change.deng.user_id, rollovers.day_of(change.deng.ts),
+                     change.differences().join(", "));
+        }
+    }
+
+    if changes.is_empty() {
+        println!("No stored attempts on days with a recorded rollover need to change");
+    } else if write {
+        let rewrites = changes.iter()
+            .map(|change| (&change.deng, &change.replayed.deng, change.replayed.breakdown.as_slice()))
+            .collect::<Vec<_>>();
+        storage::rewrite_dengs(conn, &rewrites, Source::Cli)?;
+        println!("Rewrote {} of {} stored attempts", changes.len(), total);
+    } else {
+        println!("{} of {} stored attempts would change. Run with --write to rewrite them", changes.len(), total);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use chrono::NaiveDate;
+use config::Config;
+use runner::{DayCycle, Rollovers};
+use scoring::{DengContext, ScoreItem, ScoringRules};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::SystemTime;
+use storage::NewDeng;
+use streaks::{self, SkipDays};
+use types::{Deng, MessageId};
+
+// A message from the listen channel, as the runner would have received it
+#[derive(Debug)]
+pub struct Attempt {
+    pub user_id: String,
+    pub ts: SystemTime,
+    pub successful: bool,
+    pub thread_reply: bool,
+    pub message_id: Option<MessageId>,
+    // The stored deng this attempt was read back from, if any
+    pub deng_id: Option<i32>,
+}
+
+impl Attempt {
+    pub fn from_deng(deng: &Deng) -> Self {
+        let message_id = match (&deng.channel_id, &deng.slack_ts) {
+            (&Some(ref channel_id), &Some(ref ts)) => Some(MessageId { channel_id: channel_id.clone(), ts: ts.clone() }),
+            _ => None
+        };
+
+        Attempt {
+            user_id: deng.user_id.clone(),
+            ts: deng.ts,
+            successful: deng.successful,
+            thread_reply: deng.thread_reply,
+            message_id,
+            deng_id: Some(deng.id),
+        }
+    }
+}
+
+// An attempt as it would have been stored, with its original timestamp
+#[derive(Debug)]
+pub struct Replayed {
+    pub deng: NewDeng,
+    pub breakdown: Vec<ScoreItem>,
+}
+
+impl Replayed {
+    // What the stored deng would look like, for tallying history before (or without) storing it
+    pub fn preview(&self, id: i32) -> Deng {
+        Deng {
+            id,
+            ts: self.deng.ts,
+            user_id: self.deng.user_id.clone(),
+            successful: self.deng.successful,
+            days_first_deng: self.deng.days_first_deng,
+            users_first_deng: self.deng.users_first_deng,
+            points: self.deng.points,
+            thread_reply: self.deng.thread_reply,
+            placement: self.deng.placement,
+            channel_id: self.deng.channel_id.clone(),
+            slack_ts: self.deng.slack_ts.clone(),
+        }
+    }
+}
+
+// Scores past attempts through the day cycle and scoring rules the same way the runner scores live ones.
+// Attempts must be given oldest first
+pub struct Replay {
+    rules: ScoringRules,
+    skip: SkipDays,
+    day_cycle: Option<DayCycle>,
+    rollovers: Rollovers,
+    // Days each user has denged on so far, for their streaks
+    days_denged: HashMap<String, BTreeSet<NaiveDate>>,
+    // Stored dengs that have been voided, which never count towards a streak
+    voided: HashSet<i32>,
+}
+
+impl Replay {
+    pub fn new(config: &Config, rollovers: Rollovers, voided: HashSet<i32>) -> Self {
+        Replay {
+            rules: ScoringRules::from_config(config),
+            skip: SkipDays::from_config(config),
+            day_cycle: None,
+            rollovers,
+            days_denged: HashMap::new(),
+            voided,
+        }
+    }
+
+    pub fn attempt(&mut self, attempt: Attempt) -> Replayed {
+        let day = self.rollovers.day_of(attempt.ts);
+        let mut day_cycle = match self.day_cycle.take() {
+            Some(day_cycle) => if day_cycle.day() == day { day_cycle } else { DayCycle::for_day(day) },
+            None => DayCycle::for_day(day)
+        };
+
+        let replayed = if attempt.successful {
+            let first_deng = day_cycle.first_deng();
+            let denged_today = day_cycle.has_denged_today(&attempt.user_id);
+            let placement = day_cycle.placement(&attempt.user_id);
+            day_cycle.register_deng(&attempt.user_id);
+
+            // A voided deng still had its own streak worked out when it was scored, but later dengs don't build on it
+            let voided = attempt.deng_id.map_or(false, |id| self.voided.contains(&id));
+            let days = self.days_denged.entry(attempt.user_id.clone()).or_insert_with(BTreeSet::new);
+            let streak = if voided {
+                let mut with_today = days.clone();
+                with_today.insert(day);
+                streaks::calculate(&with_today, day, &self.skip).current
+            } else {
+                days.insert(day);
+                streaks::calculate(days, day, &self.skip).current
+            };
+
+            let context = DengContext {
+                successful: true,
+                days_first_deng: first_deng,
+                users_first_deng: denged_today,
+                placement,
+                streak,
+            };
+            let breakdown = self.rules.score(&context);
+
+            let deng = NewDeng::new_success(attempt.user_id, attempt.ts, attempt.message_id, first_deng, denged_today, placement, &breakdown);
+            Replayed { deng, breakdown }
+        } else {
+            let deng = NewDeng::new_failure(attempt.user_id, attempt.ts, attempt.message_id, attempt.thread_reply);
+            Replayed { deng, breakdown: vec![] }
+        };
+
+        self.day_cycle = Some(day_cycle);
+        replayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scoring;
+    use std::time::Duration;
+
+    fn config() -> Config {
+        let mut config = Config::from_env();
+        config.scoring_rules = vec![String::from("streak_milestones")];
+        config.streak_milestones = vec![String::from("3:10")];
+        config.streak_skip_weekdays = vec![];
+        config.streak_skip_dates = vec![];
+        config
+    }
+
+    fn attempt(deng_id: i32, date: u32) -> Attempt {
+        Attempt {
+            user_id: String::from("alice"),
+            // Noon in Perth on a day in July 2018, well clear of the rollover
+            ts: SystemTime::from(DayCycle::for_day(NaiveDate::from_ymd(2018, 7, date)).start()) + Duration::from_secs(3 * 60 * 60),
+            successful: true,
+            thread_reply: false,
+            message_id: None,
+            deng_id: Some(deng_id),
+        }
+    }
+
+    fn points(voided: Vec<i32>) -> Vec<i32> {
+        let mut replay = Replay::new(&config(), Rollovers::default(), voided.into_iter().collect());
+        vec![attempt(1, 2), attempt(2, 3), attempt(3, 4)].into_iter()
+            .map(|attempt| scoring::total(&replay.attempt(attempt).breakdown))
+            .collect()
+    }
+
+    #[test]
+    fn voided_dengs_do_not_extend_a_streak() {
+        assert_eq!(points(vec![]), vec![0, 0, 10]);
+        assert_eq!(points(vec![2]), vec![0, 0, 0]);
+    }
+}
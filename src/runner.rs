@@ -1,29 +1,65 @@
-use std::sync::mpsc::{self, Receiver};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::thread;
-use storage;
+use storage::{self, NewRolloverWindow, Source};
 use diesel::pg::PgConnection;
+use diesel::QueryResult;
 use types::*;
 use denghandler::DengHandler;
+use events::{DengEvent, EventStream};
+use config::{Config, SharedConfig};
+use webhooks::Webhooks;
+use scoring::{DengContext, ScoringRules};
+use streaks::{self, SkipDays};
+use seasons;
+use ratings;
+use teams;
+use daily;
+use anticheat;
+use metrics;
+use ratelimit::{Decision, Limits, RateLimiter};
+use achievements::{self, AchievementContext};
+use announce;
+use catchup;
+use replay::Attempt;
 use slack;
 use hyper;
 use command;
 use r2d2_diesel::ConnectionManager;
 use r2d2::Pool as ConnectionPool;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 use rand::{Rng, thread_rng};
 use std::fmt::{self, Debug, Formatter};
-use chrono::{self, FixedOffset, DateTime, Datelike, Timelike};
+use chrono::{self, FixedOffset, DateTime, NaiveDate, TimeZone, Timelike};
+
+// How often to check whether the day has ended while nobody is denging
+const ROLLOVER_CHECK_SECS: u64 = 60;
 
 pub struct Runner {
     day_cycle: DayCycle,
-    db_conn_pool: ConnectionPool<ConnectionManager<PgConnection>>
+    db_conn_pool: ConnectionPool<ConnectionManager<PgConnection>>,
+    events: EventStream,
+    webhooks: Webhooks,
+    config: SharedConfig,
+    meta_channel_id: Option<String>,
+    // Only known once we've seen a rollover happen
+    rollover_window: Option<NewRolloverWindow>,
+    rollovers: Rollovers,
+    rate_limiter: RateLimiter
 }
 
 impl Runner {
-    pub fn new(db_conn_pool: ConnectionPool<ConnectionManager<PgConnection>>) -> Self {
+    pub fn new(db_conn_pool: ConnectionPool<ConnectionManager<PgConnection>>, config: SharedConfig) -> Self {
         Runner {
             day_cycle: DayCycle::new(),
-            db_conn_pool
+            webhooks: Webhooks::new(config.clone(), db_conn_pool.clone()),
+            db_conn_pool,
+            events: EventStream::new(),
+            config,
+            meta_channel_id: None,
+            rollover_window: None,
+            rollovers: Rollovers::default(),
+            rate_limiter: RateLimiter::new()
         }
     }
 
@@ -31,32 +67,53 @@ impl Runner {
         let (tx, rx) = mpsc::channel();
         let (info_tx, info_rx) = mpsc::channel();
 
-        let handler = DengHandler::new(tx.clone(), info_tx.clone());
+        let last_ts = match self.db_conn_pool.get() {
+            Ok(conn) => {
+                match Rollovers::load(&conn) {
+                    Ok(rollovers) => self.rollovers = rollovers,
+                    Err(e) => error!("Could not load past rollovers: {}", e)
+                }
+
+                // Today's window was recorded before a restart, so keep checking against it
+                match storage::load_rollover_window(&conn, self.day_cycle.day()) {
+                    Ok(window) => self.rollover_window = window,
+                    Err(e) => error!("Could not load today's rollover window: {}", e)
+                }
+
+                storage::last_slack_ts(&conn).unwrap_or_else(|e| {
+                    error!("Could not load the last stored message: {}", e);
+                    None
+                })
+            },
+            Err(e) => {
+                error!("Could not get connection to DB: {}", e);
+                None
+            }
+        };
+
+        let handler = DengHandler::new(tx.clone(), info_tx.clone(), self.config.clone(), last_ts);
         self.launch_client(handler, String::from(api_key));
 
         self.launch_command_listener(&info_rx, listen_port);
+        self.sync_teams();
         self.run(&rx);
     }
 
-    fn launch_command_listener(&self, info_rx: &Receiver<SlackInfo>, listen_port: &str) {
+    fn launch_command_listener(&mut self, info_rx: &Receiver<SlackInfo>, listen_port: &str) {
         let addr = format!("0.0.0.0:{}", listen_port).parse().expect("Listen port is not valid");
-        let pool = self.db_conn_pool.clone();
 
         info!("Starting command listener on {}", &addr);
 
         // Wait for the client thread to connect to the server and give us our info
         let info = info_rx.recv().expect("Client died without sending us Slack info!");
+        self.meta_channel_id = Some(info.meta_channel_id.clone());
+        let listener = command::CommandListener::new(info, self.db_conn_pool.clone(), self.events.clone(), self.config.clone());
 
         thread::spawn(move || {
             loop {
-                let (info_in, pool_in) = (info.clone(), pool.clone());
+                let listener_in = listener.clone();
                 let server = hyper::server::Http::new()
-                    .bind(&addr, move || {
-                        match pool_in.get() {
-                            Ok(db_conn) => Ok(command::CommandListener::new(info_in.clone(), db_conn)),
-                            Err(e) => Err(::std::io::Error::new(::std::io::ErrorKind::TimedOut, e))
-                        }
-                    });
+                    .bind(&addr, move || Ok(listener_in.clone()));
 
                 match server {
                     Ok(serv) => {
@@ -100,37 +157,229 @@ impl Runner {
 
     fn run(&mut self, rx: &Receiver<Broadcast>) {
         loop {
-            match rx.recv().expect("Receiver channel broken!") {
-                Broadcast::Deng(user_id) => self.handle_deng(user_id),
-                Broadcast::NonDeng(user_id) => self.handle_non_deng(user_id)
+            match rx.recv_timeout(Duration::from_secs(ROLLOVER_CHECK_SECS)) {
+                Ok(Broadcast::Deng { user_id, ts, message_id }) => {
+                    // Throttled users still get their dengs, unless they've been muted
+                    if self.rate_limit(&user_id) != Decision::Muted {
+                        self.handle_deng(user_id, ts, message_id);
+                    }
+                },
+                Ok(Broadcast::NonDeng { user_id, ts, thread_reply, message_id }) => {
+                    // Throttled failures are still stored so spamming can't dodge penalties or anti-cheat
+                    let publish = self.rate_limit(&user_id) == Decision::Allow;
+                    self.handle_non_deng(user_id, ts, thread_reply, message_id, publish);
+                },
+                Ok(Broadcast::Missed { attempts }) => self.handle_missed(attempts),
+                Err(RecvTimeoutError::Timeout) => self.check_rollover(),
+                Err(RecvTimeoutError::Disconnected) => panic!("Receiver channel broken!")
             };
         }
     }
 
-    fn handle_deng(&mut self, user_id: String) {
+    fn rate_limit(&mut self, user_id: &str) -> Decision {
+        let limits = Limits::from_config(&self.config.get());
+        self.rate_limiter.check(user_id, &limits)
+    }
+
+    fn check_rollover(&mut self) {
         if self.day_cycle.has_ended() {
             self.day_cycle.new_day();
+            self.start_day();
+        }
+    }
+
+    // Anything that happens once a day, as soon as the new day starts
+    fn start_day(&mut self) {
+        self.sync_teams();
+
+        match self.db_conn_pool.get() {
+            Ok(conn) => {
+                let config = self.config.get();
+
+                self.rollover_window = match anticheat::record_window(&conn, &self.day_cycle, &config) {
+                    Ok(window) => {
+                        self.rollovers.record(window.day, window.rolled_over);
+                        Some(window)
+                    },
+                    Err(e) => {
+                        error!("Could not store rollover window: {}", e);
+                        None
+                    }
+                };
+
+                if config.daily_post {
+                    match daily::build_post(&conn, &config, DayCycle::today().pred()) {
+                        Ok(post) => self.announce(&post),
+                        Err(e) => error!("Could not build daily post: {}", e)
+                    }
+                }
+
+                match seasons::roll_over(&conn, &config) {
+                    Ok(Some(announcement)) => self.announce(&announcement),
+                    Ok(None) => {},
+                    Err(e) => error!("Could not roll over season: {}", e)
+                }
+
+                match ratings::update(&conn, &config) {
+                    Ok(0) => {},
+                    Ok(count) => info!("Updated {} ratings", count),
+                    Err(e) => error!("Could not update ratings: {}", e)
+                }
+            },
+            Err(e) => error!("Could not get connection to DB: {}", e)
         }
+    }
+
+    // Loading Slack user groups can be slow, so this runs on its own thread rather than holding up dengs
+    fn sync_teams(&self) {
+        let db_conn_pool = self.db_conn_pool.clone();
+        let config = self.config.get();
+
+        thread::spawn(move || {
+            match db_conn_pool.get() {
+                Ok(conn) => {
+                    if let Err(e) = teams::sync(&conn, &config, Source::Scheduler) {
+                        error!("Could not update teams: {}", e);
+                    }
+                },
+                Err(e) => error!("Could not get connection to DB: {}", e)
+            }
+        });
+    }
+
+    fn announce(&self, text: &str) {
+        match self.meta_channel_id {
+            Some(ref channel_id) => {
+                if let Err(e) = announce::post(channel_id, text) {
+                    error!("Could not post announcement: {}", e);
+                }
+            },
+            None => warn!("Not connected to Slack yet, dropping announcement: {}", text)
+        }
+    }
+
+    fn handle_deng(&mut self, user_id: String, ts: SystemTime, message_id: Option<MessageId>) {
+        self.check_rollover();
 
         let first_deng = self.day_cycle.first_deng();
         let denged_today = self.day_cycle.has_denged_today(&user_id);
+        let placement = self.day_cycle.placement(&user_id);
         self.day_cycle.register_deng(&user_id);
 
         match &self.db_conn_pool.get() {
             Ok(conn) => {
-                if let Err(e) = storage::store_success(conn, user_id, first_deng, denged_today) {
-                    error!("Could not store successful deng: {}", e);
+                let config = self.config.get();
+
+                let history = match storage::load_user_history(conn, &user_id) {
+                    Ok(dengs) => Some(dengs),
+                    Err(e) => {
+                        error!("Could not load the user's previous dengs: {}", e);
+                        None
+                    }
+                };
+                let today = self.day_cycle.day();
+                let streak = history.as_ref()
+                    .map_or(1, |dengs| streaks::with_today(dengs, &self.rollovers, today, &SkipDays::from_config(&config)).current);
+
+                let context = DengContext {
+                    successful: true,
+                    days_first_deng: first_deng,
+                    users_first_deng: denged_today,
+                    placement,
+                    streak,
+                };
+                let breakdown = ScoringRules::from_config(&config).score(&context);
+
+                match storage::store_success(conn, user_id, ts, message_id, first_deng, denged_today, placement, &breakdown, Source::Rtm) {
+                    Ok(None) => {
+                        info!("Dropping a deng that has already been stored");
+                        metrics::increment(&metrics::DUPLICATES_DROPPED);
+                    },
+                    Ok(Some(deng)) => {
+                        metrics::increment(&metrics::DENGS_STORED);
+
+                        // Checked before anyone hears about the deng, so a disqualified one goes out as worth nothing
+                        if self.check_anticheat(conn, &config, &deng) {
+                            let voided = Deng { points: 0, ..deng };
+                            self.events.publish(&DengEvent::from_deng(&voided));
+                            self.webhooks.notify(&voided);
+                            return;
+                        }
+
+                        self.events.publish(&DengEvent::from_deng(&deng));
+                        self.webhooks.notify(&deng);
+
+                        if let Some(ref history) = history {
+                            let context = AchievementContext {
+                                deng: &deng,
+                                history,
+                                rolled_over: self.day_cycle.rolled_over(),
+                                skip: SkipDays::from_config(&config),
+                            };
+                            match achievements::unlock(conn, &context) {
+                                Ok(announcements) => for announcement in announcements {
+                                    self.announce(&announcement);
+                                },
+                                Err(e) => error!("Could not check achievements: {}", e)
+                            }
+                        }
+                    },
+                    Err(e) => error!("Could not store successful deng: {}", e)
+                }
+            },
+            Err(e) => error!("Could not get connection to DB: {}", e)
+        }
+    }
+
+    // Attempts from earlier days aren't live, so nobody is told about them. Rate limits don't apply either
+    fn handle_missed(&mut self, attempts: Vec<Attempt>) {
+        match self.db_conn_pool.get() {
+            Ok(conn) => {
+                match catchup::store_missed(&conn, &self.config.get(), &self.rollovers, attempts) {
+                    Ok(stored) => info!("Stored {} attempts missed on earlier days", stored),
+                    Err(e) => error!("Could not store attempts missed on earlier days: {}", e)
                 }
             },
             Err(e) => error!("Could not get connection to DB: {}", e)
         }
     }
 
-    fn handle_non_deng(&mut self, user_id: String) {
+    // Flags bursts of attempts around the rollover. Returns true if the deng was voided for it
+    fn check_anticheat(&self, conn: &PgConnection, config: &Config, deng: &Deng) -> bool {
+        let window = match self.rollover_window {
+            Some(ref window) => window,
+            None => return false
+        };
+
+        match anticheat::check(conn, config, window, deng) {
+            Ok(Some(flag)) => {
+                self.announce(&flag.message);
+                flag.voided
+            },
+            Ok(None) => false,
+            Err(e) => {
+                error!("Could not check for rollover gaming: {}", e);
+                false
+            }
+        }
+    }
+
+    fn handle_non_deng(&mut self, user_id: String, ts: SystemTime, thread_reply: bool, message_id: Option<MessageId>, publish: bool) {
         match &self.db_conn_pool.get() {
             Ok(conn) => {
-                if let Err(e) = storage::store_failure(conn, user_id) {
-                    error!("Could not store failed deng: {}", e);
+                match storage::store_failure(conn, user_id, ts, message_id, thread_reply, Source::Rtm) {
+                    Ok(None) => {
+                        info!("Dropping a failed attempt that has already been stored");
+                        metrics::increment(&metrics::DUPLICATES_DROPPED);
+                    },
+                    Ok(Some(deng)) => {
+                        metrics::increment(&metrics::FAILURES_STORED);
+                        self.check_anticheat(conn, &self.config.get(), &deng);
+                        if publish {
+                            self.events.publish(&DengEvent::from_deng(&deng));
+                        }
+                    },
+                    Err(e) => error!("Could not store failed deng: {}", e)
                 }
             },
             Err(e) => error!("Could not get connection to DB: {}", e)
@@ -141,6 +390,8 @@ impl Runner {
 pub struct DayCycle {
     start: DateTime<FixedOffset>,
     end: DateTime<FixedOffset>,
+    // When this day actually began, which is after the start if the previous day was fuzzed
+    rolled_over: DateTime<FixedOffset>,
     denged_today: Vec<String>,
 }
 
@@ -154,6 +405,19 @@ impl DayCycle {
         DayCycle {
             start,
             end,
+            rolled_over: start,
+            denged_today: vec![],
+        }
+    }
+
+    // A past day, for replaying history. The fuzz on past days was never recorded, so it ends at exactly 9am
+    pub fn for_day(day: NaiveDate) -> Self {
+        let start = Self::offset().from_local_datetime(&day.and_hms(9, 0, 0)).unwrap();
+
+        DayCycle {
+            start,
+            end: start + chrono::Duration::days(1),
+            rolled_over: start,
             denged_today: vec![],
         }
     }
@@ -161,8 +425,10 @@ impl DayCycle {
     pub fn new_day(&mut self) {
         self.denged_today.clear();
 
+        let previous_end = self.end;
         self.start = Self::generate_day();
         self.end = Self::calculate_end(self.start);
+        self.rolled_over = previous_end.max(self.start);
 
         info!("Starting new day @ {:?} and ending @ {:?}", self.start, self.end);
     }
@@ -171,6 +437,18 @@ impl DayCycle {
         self.end < Self::now()
     }
 
+    pub fn start(&self) -> DateTime<FixedOffset> {
+        self.start
+    }
+
+    pub fn rolled_over(&self) -> DateTime<FixedOffset> {
+        self.rolled_over
+    }
+
+    pub fn day(&self) -> NaiveDate {
+        Self::day_of(SystemTime::from(self.start))
+    }
+
     // End in one days' time
     // Add in up to 15 minutes' fuzz to prevent gaming the system
     fn calculate_end(start: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
@@ -184,7 +462,7 @@ impl DayCycle {
         let mut time = Self::now();
         // Rewind to start the day yesterday if we are currently in the morning
         if time.hour() < 9 {
-            time = time.with_day(time.day() - 1).unwrap();
+            time = time - chrono::Duration::days(1);
         }
         // Set to 9am
         time.with_hour(9).unwrap()
@@ -192,10 +470,37 @@ impl DayCycle {
             .with_second(0).unwrap()
     }
 
+    // Start of the day we are currently in, ignoring any fuzz on the previous day's end
+    pub fn current_start() -> DateTime<FixedOffset> {
+        Self::generate_day()
+    }
+
     // Returns Perth time (GMT+8)
     fn now() -> DateTime<FixedOffset> {
-        let offset = FixedOffset::east(8 * 3600);
-        chrono::Utc::now().with_timezone(&offset)
+        chrono::Utc::now().with_timezone(&Self::offset())
+    }
+
+    pub fn offset() -> FixedOffset {
+        FixedOffset::east(8 * 3600)
+    }
+
+    pub fn local(time: SystemTime) -> DateTime<FixedOffset> {
+        chrono::DateTime::<chrono::Utc>::from(time).with_timezone(&Self::offset())
+    }
+
+    // The day a past deng belongs to. Days are counted from 9am, without the fuzz on the end
+    pub fn day_of(time: SystemTime) -> NaiveDate {
+        (Self::local(time) - chrono::Duration::hours(9)).date().naive_local()
+    }
+
+    // How long after its day's 9am rollover a deng was
+    pub fn since_rollover(time: SystemTime) -> chrono::Duration {
+        let shifted = Self::local(time) - chrono::Duration::hours(9);
+        chrono::Duration::seconds(shifted.time().num_seconds_from_midnight() as i64)
+    }
+
+    pub fn today() -> NaiveDate {
+        Self::day_of(SystemTime::now())
     }
 
     pub fn has_denged_today(&self, user_id: &str) -> bool {
@@ -206,8 +511,50 @@ impl DayCycle {
         self.denged_today.is_empty()
     }
 
+    // 1 for the first person to deng today, 2 for the second and so on.
+    // None if the user has already denged today
+    pub fn placement(&self, user_id: &str) -> Option<i32> {
+        if self.denged_today.iter().any(|id| id.as_str() == user_id) {
+            None
+        } else {
+            Some(self.denged_today.len() as i32 + 1)
+        }
+    }
+
+    // Users are kept in the order they first denged in
     pub fn register_deng(&mut self, user_id: &str) {
-        self.denged_today.push(String::from(user_id));
+        if !self.denged_today.iter().any(|id| id.as_str() == user_id) {
+            self.denged_today.push(String::from(user_id));
+        }
+    }
+}
+
+// When past days actually rolled over, where that was recorded, so dengs made during the fuzz on the end of a day
+// still count towards it. Days before rollovers were recorded are assumed to have rolled over at exactly 9am
+#[derive(Clone, Debug, Default)]
+pub struct Rollovers {
+    times: HashMap<NaiveDate, SystemTime>,
+}
+
+impl Rollovers {
+    pub fn load(conn: &PgConnection) -> QueryResult<Self> {
+        Ok(Rollovers { times: storage::load_rollovers(conn)?.into_iter().collect() })
+    }
+
+    pub fn record(&mut self, day: NaiveDate, rolled_over: SystemTime) {
+        self.times.insert(day, rolled_over);
+    }
+
+    pub fn is_recorded(&self, day: NaiveDate) -> bool {
+        self.times.contains_key(&day)
+    }
+
+    pub fn day_of(&self, time: SystemTime) -> NaiveDate {
+        let day = DayCycle::day_of(time);
+        match self.times.get(&day) {
+            Some(&rolled_over) if time < rolled_over => day.pred(),
+            _ => day
+        }
     }
 }
 
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+use chrono::{self, NaiveDate};
+use config::Config;
+use runner::DayCycle;
+use slack;
+use types::{Adjustment, Deng, Error, HotScore};
+
+#[derive(Debug)]
+pub struct Standing {
+    pub user_id: String,
+    // Net of any penalties
+    pub points: i32,
+    pub penalty: i32,
+}
+
+// Optional penalties for failed attempts. They are worked out from the stored failures
+// whenever scores are tallied, so old data is penalised the same way as new
+#[derive(Clone, Copy, Debug)]
+pub struct Penalties {
+    points: i32,
+    daily_cap: i32,
+    thread_grace: bool,
+}
+
+impl Penalties {
+    pub fn from_config(config: &Config) -> Self {
+        Penalties {
+            points: config.penalty_points,
+            daily_cap: config.penalty_daily_cap,
+            thread_grace: config.penalty_thread_grace,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.points > 0
+    }
+
+    fn applies_to(&self, deng: &Deng) -> bool {
+        !deng.successful && !(self.thread_grace && deng.thread_reply)
+    }
+
+    // Each user's failures are penalised per day, up to the daily cap
+    fn calculate<'a, I>(&self, failures: I) -> HashMap<&'a str, i32> where I: Iterator<Item=&'a Deng> {
+        let per_day = failures.fold(HashMap::new(), |mut map, deng| {
+            *map.entry((deng.user_id.as_str(), DayCycle::day_of(deng.ts))).or_insert(0) += self.points;
+            map
+        });
+
+        per_day.into_iter()
+            .fold(HashMap::new(), |mut map, ((user_id, _), penalty)| {
+                let capped = if self.daily_cap > 0 { penalty.min(self.daily_cap) } else { penalty };
+                *map.entry(user_id).or_insert(0) += capped;
+                map
+            })
+    }
+}
+
+// Shared by the slash command and the web leaderboard so both always agree.
+// Voided dengs are skipped, any other adjustments are added on top and penalties are taken off.
+pub fn tally<'a, D, A>(dengs: D, adjustments: A, penalties: &Penalties) -> Vec<Standing>
+    where D: IntoIterator<Item=&'a Deng>, A: IntoIterator<Item=&'a Adjustment> {
+
+    let adjustments = adjustments.into_iter().collect::<Vec<_>>();
+    let voided = adjustments.iter()
+        .filter_map(|adjustment| adjustment.deng_id)
+        .collect::<HashSet<_>>();
+
+    let (successes, failures): (Vec<&Deng>, Vec<&Deng>) = dengs
+        .into_iter()
+        .filter(|deng| !voided.contains(&deng.id))
+        .partition(|deng| deng.successful);
+
+    let mut scores = successes
+        .into_iter()
+        .fold(HashMap::new(), |mut map, deng| {
+            *map.entry(deng.user_id.as_str()).or_insert(0) += deng.value();
+            map
+        });
+
+    for adjustment in adjustments.iter().filter(|adjustment| adjustment.deng_id.is_none()) {
+        *scores.entry(adjustment.user_id.as_str()).or_insert(0) += adjustment.points;
+    }
+
+    let user_penalties = if penalties.enabled() {
+        penalties.calculate(failures.into_iter().filter(|deng| penalties.applies_to(deng)))
+    } else {
+        HashMap::new()
+    };
+
+    for &user_id in user_penalties.keys() {
+        scores.entry(user_id).or_insert(0);
+    }
+
+    let mut standings = scores
+        .into_iter()
+        .map(|(user_id, points)| {
+            let penalty = user_penalties.get(user_id).cloned().unwrap_or(0);
+            Standing { user_id: String::from(user_id), points: points - penalty, penalty }
+        })
+        .collect::<Vec<_>>();
+
+    standings.sort_by(|first, second| second.points.cmp(&first.points));
+
+    trace!("Raw ordered score list: {:?}", standings);
+
+    standings
+}
+
+// Scores that have decayed below this would show as 0.0, so are left off
+const MIN_HOT_SCORE: f64 = 0.05;
+
+pub fn format_hot(scores: &[HotScore], half_life_hours: f64) -> String {
+    let scores = scores.iter()
+        .filter(|score| score.score >= MIN_HOT_SCORE)
+        .collect::<Vec<_>>();
+
+    match scores.len() {
+        0 => String::from("Nobody has denged lately!"),
+        _ => {
+            let lines = scores.into_iter()
+                .enumerate()
+                .map(|(rank, score)| format!("{}. <@{}> *{:.1}*", rank + 1, score.user_id, score.score))
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(":fire: *Hot Dengers* :fire:\n_Points halve in value every {} hours_\n{}", half_life_hours, lines)
+        }
+    }
+}
+
+// Each user's running total at the end of each of the given days
+pub fn history(dengs: &[Deng], adjustments: &[Adjustment], penalties: &Penalties, days: &[NaiveDate]) -> HashMap<String, Vec<i32>> {
+    let mut history = HashMap::new();
+
+    for (index, &day) in days.iter().enumerate() {
+        let standings = tally(dengs.iter().filter(|deng| DayCycle::day_of(deng.ts) <= day),
+                              adjustments.iter().filter(|adjustment| DayCycle::day_of(adjustment.ts) <= day),
+                              penalties);
+        for standing in standings {
+            history.entry(standing.user_id).or_insert_with(|| vec![0; days.len()])[index] = standing.points;
+        }
+    }
+
+    history
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Today,
+    Week,
+    Month,
+    AllTime
+}
+
+impl Period {
+    pub fn all() -> [Period; 4] {
+        [Period::Today, Period::Week, Period::Month, Period::AllTime]
+    }
+
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "today" => Some(Period::Today),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "all" => Some(Period::AllTime),
+            _ => None
+        }
+    }
+
+    pub fn param(&self) -> &'static str {
+        match *self {
+            Period::Today => "today",
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::AllTime => "all"
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Period::Today => "Today",
+            Period::Week => "Last 7 days",
+            Period::Month => "Last 30 days",
+            Period::AllTime => "All-time"
+        }
+    }
+
+    // Periods are rolling whole days, counted back from the current day's rollover
+    pub fn since(&self) -> Option<SystemTime> {
+        let days = match *self {
+            Period::Today => 0,
+            Period::Week => 6,
+            Period::Month => 29,
+            Period::AllTime => return None
+        };
+
+        let start = DayCycle::current_start() - chrono::Duration::days(days);
+        Some(SystemTime::from(start))
+    }
+}
+
+pub struct UserDisplay {
+    pub username: String,
+    pub full_name: String,
+    pub color: String,
+}
+
+impl UserDisplay {
+    pub fn find(user_list: &[slack::User], user_id: &str) -> Result<Self, Error> {
+        let user = user_list.iter()
+            .find(|user| match user.id {
+                Some(ref id) => id == user_id,
+                None => false,
+            })
+            .ok_or_else(|| Error::from("Could not find matching user"))?;
+
+        let profile = user.profile.as_ref()
+            .ok_or_else(|| Error::from("Could not find user profile"))?;
+
+        let username = profile.display_name.as_ref()
+            .ok_or_else(|| Error::from("Could not find username"))?;
+
+        let full_name = profile.real_name.as_ref()
+            .ok_or_else(|| Error::from("Could not find username"))?;
+
+        Ok(UserDisplay {
+            username: username.clone(),
+            full_name: full_name.clone(),
+            color: format!("#{}", user.color.as_ref().unwrap_or(&String::from("000000"))),
+        })
+    }
+}
@@ -0,0 +1,261 @@
+use config::Config;
+
+const DEFAULT_RULES: &[&str] = &["users_first_deng", "days_first_deng"];
+
+// Everything the rules know about a deng at the time it is scored
+#[derive(Debug)]
+pub struct DengContext {
+    pub successful: bool,
+    pub days_first_deng: bool,
+    pub users_first_deng: bool,
+    // Where the user placed among today's dengers, if this is their first deng today
+    pub placement: Option<i32>,
+    // The user's current streak in days, counting this deng
+    pub streak: u32,
+}
+
+// One line of a deng's point breakdown. These are stored with the deng so that
+// changing the rules later doesn't rewrite history
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreItem {
+    pub rule: String,
+    pub points: i32,
+}
+
+pub trait ScoringRule: Send {
+    fn name(&self) -> &str;
+
+    // Points awarded (or taken away) for this deng. Zero means the rule doesn't apply
+    fn score(&self, deng: &DengContext) -> i32;
+}
+
+// +points for the user's first deng of the day
+struct UsersFirstDeng {
+    points: i32
+}
+
+impl ScoringRule for UsersFirstDeng {
+    fn name(&self) -> &str {
+        "users_first_deng"
+    }
+
+    fn score(&self, deng: &DengContext) -> i32 {
+        if deng.successful && deng.users_first_deng { self.points } else { 0 }
+    }
+}
+
+// +points for the first deng of the day by anyone
+struct DaysFirstDeng {
+    points: i32
+}
+
+impl ScoringRule for DaysFirstDeng {
+    fn name(&self) -> &str {
+        "days_first_deng"
+    }
+
+    fn score(&self, deng: &DengContext) -> i32 {
+        if deng.successful && deng.days_first_deng { self.points } else { 0 }
+    }
+}
+
+// Points for placing 1st, 2nd or 3rd among the day's dengers
+struct Podium {
+    points: Vec<i32>
+}
+
+impl ScoringRule for Podium {
+    fn name(&self) -> &str {
+        "podium"
+    }
+
+    fn score(&self, deng: &DengContext) -> i32 {
+        match deng.placement {
+            Some(placement) if deng.successful && placement > 0 => {
+                self.points.get(placement as usize - 1).cloned().unwrap_or(0)
+            },
+            _ => 0
+        }
+    }
+}
+
+impl Podium {
+    // Points for each place are listed in PODIUM_POINTS, starting from 1st
+    fn from_config(config: &Config) -> Self {
+        let points = config.podium_points.iter()
+            .take(3)
+            .map(|points| points.parse().unwrap_or_else(|_| {
+                warn!("Invalid podium points '{}', expected a number", points);
+                0
+            }))
+            .collect();
+
+        Podium { points }
+    }
+}
+
+// Bonus points for the user's first deng on the day their streak reaches a milestone
+struct StreakMilestones {
+    milestones: Vec<(u32, i32)>
+}
+
+impl ScoringRule for StreakMilestones {
+    fn name(&self) -> &str {
+        "streak_milestones"
+    }
+
+    fn score(&self, deng: &DengContext) -> i32 {
+        if !(deng.successful && deng.users_first_deng) {
+            return 0;
+        }
+
+        self.milestones.iter()
+            .find(|&&(days, _)| days == deng.streak)
+            .map_or(0, |&(_, points)| points)
+    }
+}
+
+impl StreakMilestones {
+    // Milestones are listed in STREAK_MILESTONES as `days:points`
+    fn from_config(config: &Config) -> Self {
+        let milestones = config.streak_milestones.iter()
+            .filter_map(|spec| {
+                let mut parts = spec.splitn(2, ':');
+                match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+                    (Some(Ok(days)), Some(Ok(points))) => Some((days, points)),
+                    _ => {
+                        warn!("Invalid streak milestone '{}', expected days:points", spec);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        StreakMilestones { milestones }
+    }
+}
+
+pub struct ScoringRules {
+    rules: Vec<Box<ScoringRule>>
+}
+
+impl ScoringRules {
+    // Rules are listed in SCORING_RULES as `name` or `name:points`, and applied in that order
+    pub fn from_config(config: &Config) -> Self {
+        let rules = match config.scoring_rules.len() {
+            0 => DEFAULT_RULES.iter().filter_map(|spec| Self::build_rule(spec, config)).collect(),
+            _ => config.scoring_rules.iter().filter_map(|spec| Self::build_rule(spec, config)).collect()
+        };
+
+        ScoringRules { rules }
+    }
+
+    fn build_rule(spec: &str, config: &Config) -> Option<Box<ScoringRule>> {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+
+        let points = match parts.next().map(|points| points.trim().parse::<i32>()) {
+            Some(Ok(points)) => points,
+            Some(Err(e)) => {
+                warn!("Invalid points for scoring rule '{}', skipping it: {}", spec, e);
+                return None;
+            },
+            None => 1
+        };
+
+        match name {
+            "users_first_deng" => Some(Box::new(UsersFirstDeng { points })),
+            "days_first_deng" => Some(Box::new(DaysFirstDeng { points })),
+            "podium" => Some(Box::new(Podium::from_config(config))),
+            "streak_milestones" => Some(Box::new(StreakMilestones::from_config(config))),
+            _ => {
+                warn!("Unknown scoring rule '{}', skipping it", name);
+                None
+            }
+        }
+    }
+
+    pub fn score(&self, deng: &DengContext) -> Vec<ScoreItem> {
+        let breakdown = self.rules.iter()
+            .map(|rule| ScoreItem { rule: String::from(rule.name()), points: rule.score(deng) })
+            .filter(|item| item.points != 0)
+            .collect();
+
+        debug!("Scored {:?} as {:?}", deng, breakdown);
+
+        breakdown
+    }
+}
+
+pub fn total(breakdown: &[ScoreItem]) -> i32 {
+    breakdown.iter().map(|item| item.points).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(scoring_rules: &[&str], podium_points: &[&str]) -> Config {
+        let mut config = Config::from_env();
+        config.scoring_rules = scoring_rules.iter().map(|rule| String::from(*rule)).collect();
+        config.podium_points = podium_points.iter().map(|points| String::from(*points)).collect();
+        config.streak_milestones = vec![];
+        config
+    }
+
+    fn first_deng(placement: i32) -> DengContext {
+        DengContext {
+            successful: true,
+            days_first_deng: placement == 1,
+            users_first_deng: true,
+            placement: Some(placement),
+            streak: 1,
+        }
+    }
+
+    fn rules(breakdown: &[ScoreItem]) -> Vec<(&str, i32)> {
+        breakdown.iter().map(|item| (item.rule.as_str(), item.points)).collect()
+    }
+
+    #[test]
+    fn defaults_to_a_point_for_each_first_deng() {
+        let breakdown = ScoringRules::from_config(&config(&[], &[])).score(&first_deng(1));
+
+        assert_eq!(rules(&breakdown), vec![("users_first_deng", 1), ("days_first_deng", 1)]);
+    }
+
+    #[test]
+    fn reads_points_from_the_rule() {
+        let breakdown = ScoringRules::from_config(&config(&["days_first_deng:5", "users_first_deng: 2"], &[]))
+            .score(&first_deng(1));
+
+        assert_eq!(rules(&breakdown), vec![("days_first_deng", 5), ("users_first_deng", 2)]);
+    }
+
+    #[test]
+    fn skips_misspecified_rules() {
+        let breakdown = ScoringRules::from_config(&config(&["users_first_deng:lots", "fastest_deng", "days_first_deng"], &[]))
+            .score(&first_deng(1));
+
+        assert_eq!(rules(&breakdown), vec![("days_first_deng", 1)]);
+    }
+
+    #[test]
+    fn failures_score_nothing() {
+        let failure = DengContext { successful: false, ..first_deng(1) };
+
+        assert!(ScoringRules::from_config(&config(&[], &[])).score(&failure).is_empty());
+    }
+
+    #[test]
+    fn podium_points_go_by_placement() {
+        let rules = ScoringRules::from_config(&config(&["podium"], &["3", "2", "x"]));
+
+        assert_eq!(total(&rules.score(&first_deng(1))), 3);
+        assert_eq!(total(&rules.score(&first_deng(2))), 2);
+        // Invalid points score nothing, as does placing off the podium
+        assert_eq!(total(&rules.score(&first_deng(3))), 0);
+        assert_eq!(total(&rules.score(&first_deng(4))), 0);
+        assert_eq!(total(&rules.score(&DengContext { placement: None, ..first_deng(1) })), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{Datelike, NaiveDate};
+use config::Config;
+use diesel::PgConnection;
+use runner::DayCycle;
+use scoreboard::{self, Penalties};
+use storage::{self, Source};
+use types::{Error, Season, SeasonStanding};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeasonLength {
+    Monthly,
+    Quarterly,
+    // Seasons only end when an admin ends them
+    Manual,
+}
+
+impl SeasonLength {
+    pub fn from_config(config: &Config) -> Self {
+        match config.season_length.to_lowercase().as_str() {
+            "monthly" => SeasonLength::Monthly,
+            "quarterly" => SeasonLength::Quarterly,
+            "manual" => SeasonLength::Manual,
+            other => {
+                warn!("Invalid SEASON_LENGTH '{}', seasons will only end manually", other);
+                SeasonLength::Manual
+            }
+        }
+    }
+
+    // Whether a season that started on one day is over by another
+    fn is_over(&self, started: NaiveDate, today: NaiveDate) -> bool {
+        match *self {
+            SeasonLength::Monthly => (started.year(), started.month()) != (today.year(), today.month()),
+            SeasonLength::Quarterly => (started.year(), quarter(started)) != (today.year(), quarter(today)),
+            SeasonLength::Manual => false
+        }
+    }
+
+    fn name(&self, today: NaiveDate, number: i64) -> String {
+        match *self {
+            SeasonLength::Monthly => today.format("%B %Y").to_string(),
+            SeasonLength::Quarterly => format!("Q{} {}", quarter(today), today.year()),
+            SeasonLength::Manual => format!("Season {}", number)
+        }
+    }
+}
+
+fn quarter(day: NaiveDate) -> u32 {
+    (day.month() - 1) / 3 + 1
+}
+
+// Called when a new day starts. Returns the announcement if a season has just ended
+pub fn roll_over(conn: &PgConnection, config: &Config) -> Result<Option<String>, Error> {
+    let length = SeasonLength::from_config(config);
+
+    match storage::current_season(conn)? {
+        Some(ref season) if length.is_over(DayCycle::day_of(season.started), DayCycle::today()) => {
+            end(conn, "dengbot", season, None, config, Source::Scheduler).map(Some)
+        },
+        _ => Ok(None)
+    }
+}
+
+// Archives the season's standings and starts the next one, returning the announcement
+pub fn end(conn: &PgConnection,
+           actor: &str,
+           season: &Season,
+           name: Option<String>,
+           config: &Config,
+           source: Source) -> Result<String, Error> {
+
+    let standings = scoreboard::tally(&storage::load_since(conn, Some(season.started))?,
+                                      &storage::load_adjustments(conn, Some(season.started))?,
+                                      &Penalties::from_config(config));
+
+    let name = match name {
+        Some(name) => name,
+        None => SeasonLength::from_config(config).name(DayCycle::today(), storage::count_seasons(conn)? + 1)
+    };
+
+    let next = storage::end_season(conn, actor, season, &standings, &name, source)?;
+
+    info!("Season {} ended, starting {}", season.name, next.name);
+
+    let result = match standings.first() {
+        Some(champion) => {
+            let mut text = format!(":trophy: *{}* is over! Congratulations to <@{}>, champion with {} points",
+                                   season.name, champion.user_id, champion.points);
+            let runners_up = standings.iter()
+                .skip(1)
+                .take(2)
+                .map(|standing| format!("<@{}> ({})", standing.user_id, standing.points))
+                .collect::<Vec<_>>();
+            if !runners_up.is_empty() {
+                text.push_str(&format!(", followed by {}", runners_up.join(" and ")));
+            }
+            text
+        },
+        None => format!("*{}* is over, and nobody scored a point.", season.name)
+    };
+
+    Ok(format!("{}\n*{}* starts now. Good luck!", result, next.name))
+}
+
+pub fn format_seasons(seasons: &[Season], standings: &[SeasonStanding]) -> String {
+    if seasons.is_empty() {
+        return String::from("No seasons have finished yet.");
+    }
+
+    let lines = seasons.iter()
+        .map(|season| {
+            let champion = standings.iter()
+                .find(|standing| standing.season_id == season.id && standing.rank == 1)
+                .map_or(String::from("nobody"), |standing| format!("<@{}> ({})", standing.user_id, standing.points));
+            format!("`{}` *{}* - :trophy: {}", season.id, season.name, champion)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("*Past Seasons*\n{}\nUse `/deng season <id>` to see a season's final standings.", lines)
+}
+
+pub fn format_standings(season: &Season, standings: &[SeasonStanding]) -> String {
+    let ended = match season.ended {
+        Some(ended) => DayCycle::local(ended).format("%e %b %Y").to_string(),
+        None => String::from("now")
+    };
+
+    let lines = match standings.len() {
+        0 => String::from("Nobody scored any points."),
+        _ => standings.iter()
+            .map(|standing| format!("{}. <@{}> - {}", standing.rank, standing.user_id, standing.points))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    format!("*{}* ({} to {})\n{}", season.name, DayCycle::local(season.started).format("%e %b %Y"), ended, lines)
+}
@@ -0,0 +1,144 @@
+use chrono::{self, Datelike, NaiveDate, Weekday};
+use runner::DayCycle;
+use scoreboard::{self, Penalties};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::SystemTime;
+use streaks::{self, SkipDays, Streak};
+use types::{Adjustment, Deng};
+
+const SPARKLINE_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Everything we know about one user, worked out from their own dengs and adjustments
+pub struct UserStats {
+    pub user_id: String,
+    pub points: i32,
+    pub successes: usize,
+    pub failures: usize,
+    pub first_of_day: usize,
+    pub streak: Streak,
+    // The time of the user's first successful deng on each day they denged
+    firsts: BTreeMap<NaiveDate, SystemTime>,
+    // Points scored from dengs on each day, before adjustments and penalties
+    daily_points: BTreeMap<NaiveDate, i32>,
+}
+
+impl UserStats {
+    pub fn calculate(user_id: &str,
+                     dengs: &[Deng],
+                     adjustments: &[Adjustment],
+                     penalties: &Penalties,
+                     skip: &SkipDays) -> Self {
+
+        let voided = adjustments.iter()
+            .filter_map(|adjustment| adjustment.deng_id)
+            .collect::<HashSet<_>>();
+        let counted = dengs.iter()
+            .filter(|deng| !voided.contains(&deng.id))
+            .collect::<Vec<_>>();
+
+        let firsts = counted.iter()
+            .filter(|deng| deng.successful)
+            .fold(BTreeMap::new(), |mut map, deng| {
+                {
+                    let first = map.entry(DayCycle::day_of(deng.ts)).or_insert(deng.ts);
+                    if deng.ts < *first {
+                        *first = deng.ts;
+                    }
+                }
+                map
+            });
+
+        let days = firsts.keys().cloned().collect::<BTreeSet<_>>();
+
+        let daily_points = counted.iter()
+            .filter(|deng| deng.successful)
+            .fold(BTreeMap::new(), |mut map, deng| {
+                *map.entry(DayCycle::day_of(deng.ts)).or_insert(0) += deng.value();
+                map
+            });
+
+        UserStats {
+            user_id: String::from(user_id),
+            points: scoreboard::tally(dengs, adjustments, penalties).first().map_or(0, |standing| standing.points),
+            successes: counted.iter().filter(|deng| deng.successful).count(),
+            failures: counted.iter().filter(|deng| !deng.successful).count(),
+            first_of_day: counted.iter().filter(|deng| deng.successful && deng.days_first_deng).count(),
+            streak: streaks::calculate(&days, DayCycle::today(), skip),
+            firsts,
+            daily_points,
+        }
+    }
+
+    // Average time after the rollover of the user's first deng each day
+    pub fn average_time(&self) -> Option<chrono::Duration> {
+        match self.firsts.len() {
+            0 => None,
+            count => {
+                let total = self.firsts.values()
+                    .map(|ts| DayCycle::since_rollover(*ts).num_seconds())
+                    .sum::<i64>();
+                Some(chrono::Duration::seconds(total / count as i64))
+            }
+        }
+    }
+
+    pub fn fastest_time(&self) -> Option<chrono::Duration> {
+        self.firsts.values()
+            .map(|ts| DayCycle::since_rollover(*ts))
+            .min()
+    }
+
+    // The weekday the user has denged on most often
+    pub fn favourite_weekday(&self) -> Option<Weekday> {
+        let mut counts = [0; 7];
+        for day in self.firsts.keys() {
+            counts[day.weekday().num_days_from_monday() as usize] += 1;
+        }
+
+        let weekdays = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+        weekdays.iter()
+            .zip(counts.iter())
+            .filter(|&(_, &count)| count > 0)
+            .fold(None, |best: Option<(Weekday, i32)>, (&weekday, &count)| match best {
+                Some((_, best_count)) if best_count >= count => best,
+                _ => Some((weekday, count))
+            })
+            .map(|(weekday, _)| weekday)
+    }
+
+    // Points per day over the last few days, oldest first, drawn with Unicode blocks
+    pub fn sparkline(&self, days: i64) -> String {
+        let today = DayCycle::today();
+        let points = (0..days)
+            .rev()
+            .map(|ago| self.daily_points.get(&(today - chrono::Duration::days(ago))).cloned().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        let max = points.iter().cloned().max().unwrap_or(0).max(0);
+        points.into_iter()
+            .map(|points| match max {
+                0 => SPARKLINE_BLOCKS[0],
+                _ => {
+                    let level = points.max(0) as usize * (SPARKLINE_BLOCKS.len() - 1) / max as usize;
+                    SPARKLINE_BLOCKS[level]
+                }
+            })
+            .collect()
+    }
+
+    // Days on which both users denged, and this user got in first
+    pub fn days_ahead_of(&self, other: &UserStats) -> usize {
+        self.firsts.iter()
+            .filter(|&(day, ts)| other.firsts.get(day).map_or(false, |other_ts| ts < other_ts))
+            .count()
+    }
+}
+
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+    }
+}
@@ -1,5 +1,15 @@
-use types::Deng;
-use diesel::{RunQueryDsl, PgConnection, QueryResult};
+use types::{Adjustment, AuditEntry, Deng, DengPoints, HotScore, MessageId, Rating, Season, SeasonStanding, TeamMember, UnlockedAchievement};
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDate;
+use scoreboard::Standing;
+use config::Config;
+use scoring::{self, ScoreItem};
+use diesel::{Connection, ExpressionMethods, NullableExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, PgConnection, QueryResult};
+use diesel::dsl::{max, min, not};
+use diesel::sql_types::Double;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::Serialize;
+use serde_json;
 use std::time::SystemTime;
 
 table! {
@@ -10,6 +20,153 @@ table! {
         successful -> Bool,
         days_first_deng -> Bool,
         users_first_deng -> Bool,
+        points -> Int4,
+        thread_reply -> Bool,
+        placement -> Nullable<Int4>,
+        channel_id -> Nullable<Varchar>,
+        slack_ts -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    deng_points (id) {
+        id -> Int4,
+        deng_id -> Int4,
+        rule -> Varchar,
+        points -> Int4,
+    }
+}
+
+table! {
+    webhook_dead_letters (id) {
+        id -> Int4,
+        ts -> Timestamp,
+        url -> Varchar,
+        payload -> Text,
+        attempts -> Int4,
+        last_error -> Text,
+    }
+}
+
+table! {
+    adjustments (id) {
+        id -> Int4,
+        ts -> Timestamp,
+        admin_id -> Varchar,
+        user_id -> Varchar,
+        deng_id -> Nullable<Int4>,
+        points -> Int4,
+        reason -> Varchar,
+        undone -> Bool,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int4,
+        ts -> Timestamp,
+        actor -> Varchar,
+        action -> Varchar,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+        source -> Varchar,
+    }
+}
+
+table! {
+    seasons (id) {
+        id -> Int4,
+        name -> Varchar,
+        started -> Timestamp,
+        ended -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    season_standings (id) {
+        id -> Int4,
+        season_id -> Int4,
+        user_id -> Varchar,
+        points -> Int4,
+        rank -> Int4,
+    }
+}
+
+table! {
+    achievements (id) {
+        id -> Int4,
+        user_id -> Varchar,
+        achievement -> Varchar,
+        deng_id -> Int4,
+        unlocked -> Timestamp,
+    }
+}
+
+table! {
+    ratings (id) {
+        id -> Int4,
+        user_id -> Varchar,
+        day -> Date,
+        rating -> Float8,
+        change -> Float8,
+        placement -> Int4,
+        racers -> Int4,
+    }
+}
+
+table! {
+    team_members (id) {
+        id -> Int4,
+        team -> Varchar,
+        user_id -> Varchar,
+        joined -> Timestamp,
+        left -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    rollover_windows (id) {
+        id -> Int4,
+        day -> Date,
+        window_start -> Timestamp,
+        window_end -> Timestamp,
+        rolled_over -> Timestamp,
+    }
+}
+
+joinable!(deng_points -> dengs (deng_id));
+allow_tables_to_appear_in_same_query!(deng_points, dengs);
+
+// Keeps bulk inserts well under Postgres' limit on bind parameters
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+// What to do with imported rows whose IDs are already stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnConflict {
+    // Carry on past rows stored exactly as imported. A stored row with different contents still fails the import
+    Skip,
+    // Roll back the whole import
+    Fail,
+}
+
+// Where a state-changing action came from, recorded alongside it in the audit log
+#[derive(Clone, Copy, Debug)]
+pub enum Source {
+    Rtm,
+    SlashCommand,
+    // Jobs the bot runs by itself, such as those at the start of each day
+    Scheduler,
+    Cli,
+}
+
+impl Source {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Source::Rtm => "rtm",
+            Source::SlashCommand => "slash_command",
+            Source::Scheduler => "scheduler",
+            Source::Cli => "cli"
+        }
     }
 }
 
@@ -21,49 +178,866 @@ pub struct NewDeng {
     pub successful: bool,
     pub days_first_deng: bool,
     pub users_first_deng: bool,
+    pub points: i32,
+    pub thread_reply: bool,
+    pub placement: Option<i32>,
+    pub channel_id: Option<String>,
+    pub slack_ts: Option<String>,
 }
 
 impl NewDeng {
-    pub fn new_success(user_id: String, days_first_deng: bool, users_first_deng: bool) -> Self {
+    pub fn new_success(user_id: String,
+                       ts: SystemTime,
+                       message_id: Option<MessageId>,
+                       days_first_deng: bool,
+                       users_first_deng: bool,
+                       placement: Option<i32>,
+                       breakdown: &[ScoreItem]) -> Self {
         NewDeng {
-            ts: SystemTime::now(),
+            ts,
             user_id,
             successful: true,
             days_first_deng,
             users_first_deng,
+            points: scoring::total(breakdown),
+            thread_reply: false,
+            placement,
+            channel_id: message_id.as_ref().map(|id| id.channel_id.clone()),
+            slack_ts: message_id.map(|id| id.ts),
         }
     }
 
-    pub fn new_failure(user_id: String) -> Self {
+    pub fn new_failure(user_id: String, ts: SystemTime, message_id: Option<MessageId>, thread_reply: bool) -> Self {
         NewDeng {
-            ts: SystemTime::now(),
+            ts,
             user_id,
             successful: false,
             days_first_deng: false,
             users_first_deng: false,
+            points: 0,
+            thread_reply,
+            placement: None,
+            channel_id: message_id.as_ref().map(|id| id.channel_id.clone()),
+            slack_ts: message_id.map(|id| id.ts),
         }
     }
 }
 
-pub fn store_failure(conn: &PgConnection, user_id: String) -> QueryResult<Deng> {
+#[derive(Debug, Insertable)]
+#[table_name="deng_points"]
+pub struct NewDengPoints<'a> {
+    pub deng_id: i32,
+    pub rule: &'a str,
+    pub points: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="webhook_dead_letters"]
+pub struct NewDeadLetter<'a> {
+    pub ts: SystemTime,
+    pub url: &'a str,
+    pub payload: &'a str,
+    pub attempts: i32,
+    pub last_error: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="adjustments"]
+pub struct NewAdjustment {
+    pub ts: SystemTime,
+    pub admin_id: String,
+    pub user_id: String,
+    pub deng_id: Option<i32>,
+    pub points: i32,
+    pub reason: String,
+}
+
+impl NewAdjustment {
+    pub fn new_void(admin_id: String, deng: &Deng, reason: String) -> Self {
+        NewAdjustment {
+            ts: SystemTime::now(),
+            admin_id,
+            user_id: deng.user_id.clone(),
+            deng_id: Some(deng.id),
+            points: 0,
+            reason,
+        }
+    }
+
+    pub fn new_points(admin_id: String, user_id: String, points: i32, reason: String) -> Self {
+        NewAdjustment {
+            ts: SystemTime::now(),
+            admin_id,
+            user_id,
+            deng_id: None,
+            points,
+            reason,
+        }
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="audit_log"]
+pub struct NewAuditEntry<'a> {
+    pub ts: SystemTime,
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub source: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="seasons"]
+pub struct NewSeason<'a> {
+    pub name: &'a str,
+    pub started: SystemTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="season_standings"]
+pub struct NewSeasonStanding<'a> {
+    pub season_id: i32,
+    pub user_id: &'a str,
+    pub points: i32,
+    pub rank: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="achievements"]
+pub struct NewAchievement<'a> {
+    pub user_id: &'a str,
+    pub achievement: &'a str,
+    pub deng_id: i32,
+    pub unlocked: SystemTime,
+}
+
+impl<'a> NewAchievement<'a> {
+    pub fn new(user_id: &'a str, achievement: &'a str, deng: &Deng) -> Self {
+        NewAchievement {
+            user_id,
+            achievement,
+            deng_id: deng.id,
+            unlocked: deng.ts,
+        }
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="ratings"]
+pub struct NewRating {
+    pub user_id: String,
+    pub day: NaiveDate,
+    pub rating: f64,
+    pub change: f64,
+    pub placement: i32,
+    pub racers: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="team_members"]
+pub struct NewTeamMember<'a> {
+    pub team: &'a str,
+    pub user_id: &'a str,
+    pub joined: SystemTime,
+}
+
+// The stretch of time around a day's rollover in which bursts of attempts are suspicious
+#[derive(Debug, Queryable, Insertable)]
+#[table_name="rollover_windows"]
+pub struct NewRolloverWindow {
+    pub day: NaiveDate,
+    pub window_start: SystemTime,
+    pub window_end: SystemTime,
+    pub rolled_over: SystemTime,
+}
 
-    let deng = NewDeng::new_failure(user_id);
+// How many half-lives back to look. Anything older has decayed to almost nothing
+const HOT_HALF_LIVES: f64 = 10.0;
+
+// Returns None if a deng for the same Slack message has already been stored
+fn insert_deng(conn: &PgConnection, deng: &NewDeng) -> QueryResult<Option<Deng>> {
     ::diesel::insert_into(dengs::table)
-        .values(&deng)
+        .values(deng)
+        .on_conflict((dengs::channel_id, dengs::slack_ts))
+        .do_nothing()
         .get_result(conn)
+        .optional()
+}
+
+pub fn store_failure(conn: &PgConnection,
+                     user_id: String,
+                     ts: SystemTime,
+                     message_id: Option<MessageId>,
+                     thread_reply: bool,
+                     source: Source) -> QueryResult<Option<Deng>> {
+
+    let deng = NewDeng::new_failure(user_id, ts, message_id, thread_reply);
+    conn.transaction(|| {
+        let stored = match insert_deng(conn, &deng)? {
+            Some(stored) => stored,
+            None => return Ok(None)
+        };
+        audit(conn, &stored.user_id, "store_failure", source, None::<&Deng>, Some(&stored))?;
+        Ok(Some(stored))
+    })
 }
 
 pub fn store_success(conn: &PgConnection,
                      user_id: String,
+                     ts: SystemTime,
+                     message_id: Option<MessageId>,
                      days_first_deng: bool,
-                     users_first_deng: bool) -> QueryResult<Deng> {
+                     users_first_deng: bool,
+                     placement: Option<i32>,
+                     breakdown: &[ScoreItem],
+                     source: Source) -> QueryResult<Option<Deng>> {
 
-    let deng = NewDeng::new_success(user_id, days_first_deng, users_first_deng);
-    ::diesel::insert_into(dengs::table)
-        .values(&deng)
+    let deng = NewDeng::new_success(user_id, ts, message_id, days_first_deng, users_first_deng, placement, breakdown);
+    conn.transaction(|| {
+        let stored = match insert_deng(conn, &deng)? {
+            Some(stored) => stored,
+            None => return Ok(None)
+        };
+
+        insert_points(conn, stored.id, breakdown)?;
+
+        audit(conn, &stored.user_id, "store_deng", source, None::<&Deng>, Some(&stored))?;
+        Ok(Some(stored))
+    })
+}
+
+fn insert_points(conn: &PgConnection, deng_id: i32, breakdown: &[ScoreItem]) -> QueryResult<()> {
+    let points = breakdown.iter()
+        .map(|item| NewDengPoints { deng_id, rule: &item.rule, points: item.points })
+        .collect::<Vec<_>>();
+    if !points.is_empty() {
+        ::diesel::insert_into(deng_points::table)
+            .values(&points)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+// Stores replayed history in one transaction, skipping messages that are already stored.
+// Each attempt comes with its point breakdown. Returns how many were new
+pub fn store_replayed(conn: &PgConnection, replayed: &[(&NewDeng, &[ScoreItem])], source: Source) -> QueryResult<usize> {
+    conn.transaction(|| {
+        let mut stored = 0;
+        for &(new_deng, breakdown) in replayed {
+            if let Some(deng) = insert_deng(conn, new_deng)? {
+                insert_points(conn, deng.id, breakdown)?;
+                stored += 1;
+            }
+        }
+
+        audit(conn, "dengbot", "backfill", source, None::<&usize>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+// Everything stored for one user, or within a range of times, for exporting
+pub fn export_dengs(conn: &PgConnection, from: Option<SystemTime>, to: Option<SystemTime>, user_id: Option<&str>) -> QueryResult<Vec<Deng>> {
+    let mut query = dengs::table.order(dengs::id.asc()).into_boxed();
+    if let Some(from) = from {
+        query = query.filter(dengs::ts.ge(from));
+    }
+    if let Some(to) = to {
+        query = query.filter(dengs::ts.lt(to));
+    }
+    if let Some(user_id) = user_id {
+        query = query.filter(dengs::user_id.eq(user_id));
+    }
+    query.load::<Deng>(conn)
+}
+
+// Breakdowns of the dengs that `export_dengs` would export with the same filters
+pub fn export_deng_points(conn: &PgConnection,
+                          from: Option<SystemTime>,
+                          to: Option<SystemTime>,
+                          user_id: Option<&str>) -> QueryResult<Vec<DengPoints>> {
+    let mut query = deng_points::table
+        .inner_join(dengs::table)
+        .select(deng_points::all_columns)
+        .order(deng_points::id.asc())
+        .into_boxed();
+    if let Some(from) = from {
+        query = query.filter(dengs::ts.ge(from));
+    }
+    if let Some(to) = to {
+        query = query.filter(dengs::ts.lt(to));
+    }
+    if let Some(user_id) = user_id {
+        query = query.filter(dengs::user_id.eq(user_id));
+    }
+    query.load::<DengPoints>(conn)
+}
+
+// Undone adjustments are included, so an import restores the full history
+pub fn export_adjustments(conn: &PgConnection,
+                          from: Option<SystemTime>,
+                          to: Option<SystemTime>,
+                          user_id: Option<&str>) -> QueryResult<Vec<Adjustment>> {
+    let mut query = adjustments::table.order(adjustments::id.asc()).into_boxed();
+    if let Some(from) = from {
+        query = query.filter(adjustments::ts.ge(from));
+    }
+    if let Some(to) = to {
+        query = query.filter(adjustments::ts.lt(to));
+    }
+    if let Some(user_id) = user_id {
+        query = query.filter(adjustments::user_id.eq(user_id));
+    }
+    query.load::<Adjustment>(conn)
+}
+
+// Imported rows that aren't stored yet. Rows stored exactly as imported are left out, but a stored row with the
+// same ID and anything else in it is an error, as the rows that refer to it would end up pointing at the wrong thing
+fn unstored<T, F>(name: &str, chunk: &[T], existing: Vec<T>, id: F) -> QueryResult<Vec<T>> where T: Clone + PartialEq, F: Fn(&T) -> i32 {
+    let existing = existing.into_iter().map(|row| (id(&row), row)).collect::<HashMap<_, _>>();
+    let mut rows = vec![];
+    for row in chunk {
+        match existing.get(&id(row)) {
+            Some(stored) if stored != row => {
+                let message = format!("Imported {} {} differs from the one already stored with that ID", name, id(row));
+                return Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(message)));
+            },
+            Some(_) => {},
+            None => rows.push(row.clone())
+        }
+    }
+    Ok(rows)
+}
+
+// Stores exported dengs with their original IDs, all or nothing. Returns how many were new.
+// A new deng for a Slack message that is already stored under another ID also fails the import
+pub fn import_dengs(conn: &PgConnection, imported: &[Deng], on_conflict: OnConflict, source: Source) -> QueryResult<usize> {
+    conn.transaction(|| {
+        let mut stored = 0;
+        for chunk in imported.chunks(INSERT_CHUNK_SIZE) {
+            let rows = if on_conflict == OnConflict::Skip {
+                let ids = chunk.iter().map(|deng| deng.id).collect::<Vec<_>>();
+                let existing = dengs::table.filter(dengs::id.eq_any(ids)).load::<Deng>(conn)?;
+                unstored("deng", chunk, existing, |deng| deng.id)?
+            } else {
+                chunk.to_vec()
+            };
+            if !rows.is_empty() {
+                stored += ::diesel::insert_into(dengs::table).values(&rows).execute(conn)?;
+            }
+        }
+
+        // Rows came with their own IDs, so the sequence has to catch up before anything else is stored
+        ::diesel::sql_query("SELECT setval('dengs_id_seq', (SELECT MAX(id) FROM dengs))").execute(conn)?;
+        audit(conn, "dengbot", "import_dengs", source, None::<&usize>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+pub fn import_deng_points(conn: &PgConnection, imported: &[DengPoints], on_conflict: OnConflict, source: Source) -> QueryResult<usize> {
+    conn.transaction(|| {
+        let mut stored = 0;
+        for chunk in imported.chunks(INSERT_CHUNK_SIZE) {
+            let rows = if on_conflict == OnConflict::Skip {
+                let ids = chunk.iter().map(|points| points.id).collect::<Vec<_>>();
+                let existing = deng_points::table.filter(deng_points::id.eq_any(ids)).load::<DengPoints>(conn)?;
+                unstored("deng point", chunk, existing, |points| points.id)?
+            } else {
+                chunk.to_vec()
+            };
+            if !rows.is_empty() {
+                stored += ::diesel::insert_into(deng_points::table).values(&rows).execute(conn)?;
+            }
+        }
+
+        ::diesel::sql_query("SELECT setval('deng_points_id_seq', (SELECT MAX(id) FROM deng_points))").execute(conn)?;
+        audit(conn, "dengbot", "import_deng_points", source, None::<&usize>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+pub fn import_adjustments(conn: &PgConnection, imported: &[Adjustment], on_conflict: OnConflict, source: Source) -> QueryResult<usize> {
+    conn.transaction(|| {
+        let mut stored = 0;
+        for chunk in imported.chunks(INSERT_CHUNK_SIZE) {
+            let rows = if on_conflict == OnConflict::Skip {
+                let ids = chunk.iter().map(|adjustment| adjustment.id).collect::<Vec<_>>();
+                let existing = adjustments::table.filter(adjustments::id.eq_any(ids)).load::<Adjustment>(conn)?;
+                unstored("adjustment", chunk, existing, |adjustment| adjustment.id)?
+            } else {
+                chunk.to_vec()
+            };
+            if !rows.is_empty() {
+                stored += ::diesel::insert_into(adjustments::table).values(&rows).execute(conn)?;
+            }
+        }
+
+        ::diesel::sql_query("SELECT setval('adjustments_id_seq', (SELECT MAX(id) FROM adjustments))").execute(conn)?;
+        audit(conn, "dengbot", "import_adjustments", source, None::<&usize>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+// Rewrites the flags and points of dengs that score differently under the current rules, all or nothing.
+// Each stored deng takes the flags, points and breakdown of its replacement
+pub fn rewrite_dengs(conn: &PgConnection, rewrites: &[(&Deng, &NewDeng, &[ScoreItem])], source: Source) -> QueryResult<()> {
+    conn.transaction(|| {
+        for &(deng, replacement, breakdown) in rewrites {
+            let rewritten: Deng = ::diesel::update(dengs::table.find(deng.id))
+                .set((
+                    dengs::days_first_deng.eq(replacement.days_first_deng),
+                    dengs::users_first_deng.eq(replacement.users_first_deng),
+                    dengs::placement.eq(replacement.placement),
+                    dengs::points.eq(replacement.points),
+                ))
+                .get_result(conn)?;
+
+            ::diesel::delete(deng_points::table.filter(deng_points::deng_id.eq(deng.id)))
+                .execute(conn)?;
+            insert_points(conn, deng.id, breakdown)?;
+
+            audit(conn, "dengbot", "recompute", source, Some(deng), Some(&rewritten))?;
+        }
+        Ok(())
+    })
+}
+
+// When the bot stored its first deng, before which there may be history to backfill
+pub fn first_ts(conn: &PgConnection) -> QueryResult<Option<SystemTime>> {
+    dengs::table
+        .select(min(dengs::ts))
+        .first::<Option<SystemTime>>(conn)
+}
+
+// Slack timestamps are zero-padded to the same length, so the latest one also sorts last
+pub fn last_slack_ts(conn: &PgConnection) -> QueryResult<Option<String>> {
+    dengs::table
+        .select(max(dengs::slack_ts))
+        .first::<Option<String>>(conn)
+}
+
+// Successful and failed attempts alike
+pub fn count_attempts(conn: &PgConnection, user_id: &str, from: SystemTime, to: SystemTime) -> QueryResult<i64> {
+    dengs::table
+        .filter(dengs::user_id.eq(user_id))
+        .filter(dengs::ts.between(from, to))
+        .count()
         .get_result(conn)
 }
 
 pub fn load(conn: &PgConnection) -> QueryResult<Vec<Deng>> {
     dengs::table.load::<Deng>(conn)
-}
\ No newline at end of file
+}
+
+// Oldest first, in the order they were stored
+pub fn load_in_order(conn: &PgConnection) -> QueryResult<Vec<Deng>> {
+    dengs::table
+        .order((dengs::ts.asc(), dengs::id.asc()))
+        .load::<Deng>(conn)
+}
+
+pub fn load_since(conn: &PgConnection, since: Option<SystemTime>) -> QueryResult<Vec<Deng>> {
+    match since {
+        Some(since) => dengs::table.filter(dengs::ts.ge(since)).load::<Deng>(conn),
+        None => load(conn)
+    }
+}
+
+pub fn load_user(conn: &PgConnection, user_id: &str) -> QueryResult<Vec<Deng>> {
+    dengs::table
+        .filter(dengs::user_id.eq(user_id))
+        .order(dengs::ts.desc())
+        .load::<Deng>(conn)
+}
+
+// The user's successful dengs that haven't been voided, newest first, using the index on user and time
+pub fn load_user_history(conn: &PgConnection, user_id: &str) -> QueryResult<Vec<Deng>> {
+    dengs::table
+        .filter(dengs::user_id.eq(user_id))
+        .filter(dengs::successful.eq(true))
+        .filter(not(dengs::id.nullable().eq_any(
+            adjustments::table
+                .select(adjustments::deng_id)
+                .filter(adjustments::deng_id.is_not_null())
+                .filter(adjustments::undone.eq(false))
+        )))
+        .order(dengs::ts.desc())
+        .load::<Deng>(conn)
+}
+
+// Dengs that have been voided and not undone
+pub fn load_voided_ids(conn: &PgConnection) -> QueryResult<HashSet<i32>> {
+    adjustments::table
+        .filter(adjustments::undone.eq(false))
+        .filter(adjustments::deng_id.is_not_null())
+        .select(adjustments::deng_id)
+        .load::<Option<i32>>(conn)
+        .map(|ids| ids.into_iter().filter_map(|id| id).collect())
+}
+
+// Used to replay dengs to event stream clients that have reconnected
+pub fn load_after(conn: &PgConnection, id: i32, limit: i64) -> QueryResult<Vec<Deng>> {
+    dengs::table
+        .filter(dengs::id.gt(id))
+        .order(dengs::id.asc())
+        .limit(limit)
+        .load::<Deng>(conn)
+}
+
+pub fn store_dead_letter(conn: &PgConnection,
+                         url: &str,
+                         payload: &str,
+                         attempts: i32,
+                         last_error: &str) -> QueryResult<usize> {
+
+    let dead_letter = NewDeadLetter {
+        ts: SystemTime::now(),
+        url,
+        payload,
+        attempts,
+        last_error,
+    };
+    ::diesel::insert_into(webhook_dead_letters::table)
+        .values(&dead_letter)
+        .execute(conn)
+}
+
+pub fn load_breakdowns(conn: &PgConnection, deng_ids: &[i32]) -> QueryResult<Vec<DengPoints>> {
+    deng_points::table
+        .filter(deng_points::deng_id.eq_any(deng_ids))
+        .order(deng_points::id.asc())
+        .load::<DengPoints>(conn)
+}
+
+pub fn find(conn: &PgConnection, id: i32) -> QueryResult<Option<Deng>> {
+    dengs::table.find(id).first::<Deng>(conn).optional()
+}
+
+pub fn store_adjustment(conn: &PgConnection, adjustment: &NewAdjustment, source: Source) -> QueryResult<Adjustment> {
+    conn.transaction(|| {
+        let stored: Adjustment = ::diesel::insert_into(adjustments::table)
+            .values(adjustment)
+            .get_result(conn)?;
+        let action = if stored.deng_id.is_some() { "void" } else { "adjust" };
+        audit(conn, &stored.admin_id, action, source, None::<&Adjustment>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+// Only adjustments that are still in effect
+pub fn load_adjustments(conn: &PgConnection, since: Option<SystemTime>) -> QueryResult<Vec<Adjustment>> {
+    let query = adjustments::table.filter(adjustments::undone.eq(false));
+    match since {
+        Some(since) => query.filter(adjustments::ts.ge(since)).load::<Adjustment>(conn),
+        None => query.load::<Adjustment>(conn)
+    }
+}
+
+pub fn load_user_adjustments(conn: &PgConnection, user_id: &str) -> QueryResult<Vec<Adjustment>> {
+    adjustments::table
+        .filter(adjustments::undone.eq(false))
+        .filter(adjustments::user_id.eq(user_id))
+        .order(adjustments::ts.desc())
+        .load::<Adjustment>(conn)
+}
+
+pub fn find_void(conn: &PgConnection, deng_id: i32) -> QueryResult<Option<Adjustment>> {
+    adjustments::table
+        .filter(adjustments::undone.eq(false))
+        .filter(adjustments::deng_id.eq(deng_id))
+        .first::<Adjustment>(conn)
+        .optional()
+}
+
+pub fn last_adjustment(conn: &PgConnection, admin_id: &str) -> QueryResult<Option<Adjustment>> {
+    adjustments::table
+        .filter(adjustments::undone.eq(false))
+        .filter(adjustments::admin_id.eq(admin_id))
+        .order(adjustments::id.desc())
+        .first::<Adjustment>(conn)
+        .optional()
+}
+
+pub fn undo_adjustment(conn: &PgConnection, actor: &str, adjustment: &Adjustment, source: Source) -> QueryResult<Adjustment> {
+    conn.transaction(|| {
+        let undone: Adjustment = ::diesel::update(adjustments::table.find(adjustment.id))
+            .set(adjustments::undone.eq(true))
+            .get_result(conn)?;
+        audit(conn, actor, "undo", source, Some(adjustment), Some(&undone))?;
+        Ok(undone)
+    })
+}
+
+pub fn audit_config_reload(conn: &PgConnection, actor: &str, before: &Config, after: &Config, source: Source) -> QueryResult<()> {
+    audit(conn, actor, "config_reload", source, Some(before), Some(after))
+}
+
+pub fn load_audit_log(conn: &PgConnection, limit: i64) -> QueryResult<Vec<AuditEntry>> {
+    audit_log::table
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .load::<AuditEntry>(conn)
+}
+
+pub fn current_season(conn: &PgConnection) -> QueryResult<Option<Season>> {
+    seasons::table
+        .filter(seasons::ended.is_null())
+        .order(seasons::id.desc())
+        .first::<Season>(conn)
+        .optional()
+}
+
+// Seasons that have finished, most recent first
+pub fn load_past_seasons(conn: &PgConnection) -> QueryResult<Vec<Season>> {
+    seasons::table
+        .filter(seasons::ended.is_not_null())
+        .order(seasons::id.desc())
+        .load::<Season>(conn)
+}
+
+pub fn find_season(conn: &PgConnection, id: i32) -> QueryResult<Option<Season>> {
+    seasons::table.find(id).first::<Season>(conn).optional()
+}
+
+pub fn count_seasons(conn: &PgConnection) -> QueryResult<i64> {
+    seasons::table.count().get_result(conn)
+}
+
+pub fn load_season_standings(conn: &PgConnection, season_ids: &[i32]) -> QueryResult<Vec<SeasonStanding>> {
+    season_standings::table
+        .filter(season_standings::season_id.eq_any(season_ids))
+        .order((season_standings::season_id.desc(), season_standings::rank.asc()))
+        .load::<SeasonStanding>(conn)
+}
+
+// Archives the season's final standings and starts the next one in a single step
+pub fn end_season(conn: &PgConnection,
+                  actor: &str,
+                  season: &Season,
+                  standings: &[Standing],
+                  next_name: &str,
+                  source: Source) -> QueryResult<Season> {
+
+    let now = SystemTime::now();
+    conn.transaction(|| {
+        let ended: Season = ::diesel::update(seasons::table.find(season.id))
+            .set(seasons::ended.eq(now))
+            .get_result(conn)?;
+
+        let archived = standings.iter()
+            .enumerate()
+            .map(|(index, standing)| NewSeasonStanding {
+                season_id: season.id,
+                user_id: &standing.user_id,
+                points: standing.points,
+                rank: index as i32 + 1,
+            })
+            .collect::<Vec<_>>();
+        if !archived.is_empty() {
+            ::diesel::insert_into(season_standings::table)
+                .values(&archived)
+                .execute(conn)?;
+        }
+
+        let next: Season = ::diesel::insert_into(seasons::table)
+            .values(&NewSeason { name: next_name, started: now })
+            .get_result(conn)?;
+
+        audit(conn, actor, "season_reset", source, Some(&ended), Some(&next))?;
+        Ok(next)
+    })
+}
+
+pub fn store_achievement(conn: &PgConnection, achievement: &NewAchievement, source: Source) -> QueryResult<UnlockedAchievement> {
+    conn.transaction(|| {
+        let stored: UnlockedAchievement = ::diesel::insert_into(achievements::table)
+            .values(achievement)
+            .get_result(conn)?;
+        audit(conn, &stored.user_id, "unlock_achievement", source, None::<&UnlockedAchievement>, Some(&stored))?;
+        Ok(stored)
+    })
+}
+
+pub fn load_achievements(conn: &PgConnection) -> QueryResult<Vec<UnlockedAchievement>> {
+    achievements::table.load::<UnlockedAchievement>(conn)
+}
+
+pub fn load_user_achievements(conn: &PgConnection, user_id: &str) -> QueryResult<Vec<UnlockedAchievement>> {
+    achievements::table
+        .filter(achievements::user_id.eq(user_id))
+        .load::<UnlockedAchievement>(conn)
+}
+
+// Decay is worked out by the database so only recent rows need to be read, using the index on ts
+pub fn load_hot_scores(conn: &PgConnection, half_life_hours: f64) -> QueryResult<Vec<HotScore>> {
+    let half_life_seconds = half_life_hours * 60.0 * 60.0;
+
+    ::diesel::sql_query("select user_id, \
+                                sum(points * power(0.5, extract(epoch from (now() at time zone 'utc') - ts) / $1))::float8 as score \
+                         from dengs \
+                         where successful \
+                           and ts > (now() at time zone 'utc') - $1 * $2 * interval '1 second' \
+                           and not exists (select 1 from adjustments where adjustments.deng_id = dengs.id and not adjustments.undone) \
+                         group by user_id \
+                         order by score desc")
+        .bind::<Double, _>(half_life_seconds)
+        .bind::<Double, _>(HOT_HALF_LIVES)
+        .load::<HotScore>(conn)
+}
+
+pub fn load_team_members(conn: &PgConnection) -> QueryResult<Vec<TeamMember>> {
+    team_members::table
+        .order(team_members::id.asc())
+        .load::<TeamMember>(conn)
+}
+
+// Closes the memberships of anyone no longer on the same team, and opens new ones for anyone who has joined or moved.
+// Syncs can run at the same time from different threads, so each waits for the last to finish before reading
+pub fn sync_team_members(conn: &PgConnection, wanted: &HashMap<String, String>, source: Source) -> QueryResult<usize> {
+    conn.transaction(|| {
+        ::diesel::sql_query("lock table team_members in share row exclusive mode").execute(conn)?;
+        let current = load_team_members(conn)?;
+        let now = SystemTime::now();
+        let mut changed = 0;
+
+        for member in current.iter().filter(|member| member.left.is_none()) {
+            if wanted.get(&member.user_id) == Some(&member.team) {
+                continue;
+            }
+
+            let left: TeamMember = ::diesel::update(team_members::table.find(member.id))
+                .set(team_members::left.eq(now))
+                .get_result(conn)?;
+            audit(conn, &member.user_id, "leave_team", source, Some(member), Some(&left))?;
+            changed += 1;
+        }
+
+        for (user_id, team) in wanted {
+            let still_member = current.iter()
+                .any(|member| member.left.is_none() && member.user_id == *user_id && member.team == *team);
+            if still_member {
+                continue;
+            }
+
+            // Points scored before joining stay out of the team's total, including for a user's first team
+            let member: TeamMember = ::diesel::insert_into(team_members::table)
+                .values(&NewTeamMember { team, user_id, joined: now })
+                .get_result(conn)?;
+            audit(conn, user_id, "join_team", source, None::<&TeamMember>, Some(&member))?;
+            changed += 1;
+        }
+
+        Ok(changed)
+    })
+}
+
+pub fn store_rollover_window(conn: &PgConnection, window: &NewRolloverWindow) -> QueryResult<usize> {
+    ::diesel::insert_into(rollover_windows::table)
+        .values(window)
+        .on_conflict(rollover_windows::day)
+        .do_nothing()
+        .execute(conn)
+}
+
+pub fn load_rollover_window(conn: &PgConnection, day: NaiveDate) -> QueryResult<Option<NewRolloverWindow>> {
+    rollover_windows::table
+        .filter(rollover_windows::day.eq(day))
+        .select((rollover_windows::day, rollover_windows::window_start, rollover_windows::window_end, rollover_windows::rolled_over))
+        .first::<NewRolloverWindow>(conn)
+        .optional()
+}
+
+// When each recorded day actually rolled over, fuzz and all
+pub fn load_rollovers(conn: &PgConnection) -> QueryResult<Vec<(NaiveDate, SystemTime)>> {
+    rollover_windows::table
+        .select((rollover_windows::day, rollover_windows::rolled_over))
+        .load::<(NaiveDate, SystemTime)>(conn)
+}
+
+pub fn audit_rollover_flag(conn: &PgConnection, deng: &Deng, attempts: i64, source: Source) -> QueryResult<()> {
+    audit(conn, "dengbot", "flag_rollover_burst", source, Some(deng), Some(&attempts))
+}
+
+// Oldest first, so later entries are each user's more recent ratings
+pub fn load_ratings(conn: &PgConnection) -> QueryResult<Vec<Rating>> {
+    ratings::table
+        .order((ratings::day.asc(), ratings::id.asc()))
+        .load::<Rating>(conn)
+}
+
+pub fn last_rated_day(conn: &PgConnection) -> QueryResult<Option<NaiveDate>> {
+    ratings::table
+        .select(max(ratings::day))
+        .first::<Option<NaiveDate>>(conn)
+}
+
+pub fn store_ratings(conn: &PgConnection, new_ratings: &[NewRating]) -> QueryResult<()> {
+    conn.transaction(|| {
+        for chunk in new_ratings.chunks(INSERT_CHUNK_SIZE) {
+            ::diesel::insert_into(ratings::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn replace_ratings(conn: &PgConnection, actor: &str, new_ratings: &[NewRating], source: Source) -> QueryResult<()> {
+    conn.transaction(|| {
+        let removed = ::diesel::delete(ratings::table).execute(conn)?;
+        store_ratings(conn, new_ratings)?;
+        audit(conn, actor, "recompute_ratings", source, Some(&removed), Some(&new_ratings.len()))
+    })
+}
+
+// The audit log is append-only: entries are only ever inserted, alongside the change they describe
+fn audit<B, A>(conn: &PgConnection,
+               actor: &str,
+               action: &str,
+               source: Source,
+               before: Option<&B>,
+               after: Option<&A>) -> QueryResult<()> where B: Serialize, A: Serialize {
+
+    let entry = NewAuditEntry {
+        ts: SystemTime::now(),
+        actor,
+        action,
+        before: to_json(before)?,
+        after: to_json(after)?,
+        source: source.as_str(),
+    };
+    ::diesel::insert_into(audit_log::table)
+        .values(&entry)
+        .execute(conn)
+        .map(|_| ())
+}
+
+fn to_json<T>(value: Option<&T>) -> QueryResult<Option<String>> where T: Serialize {
+    match value {
+        Some(value) => serde_json::to_string(value)
+            .map(Some)
+            .map_err(|e| DieselError::SerializationError(Box::new(e))),
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(id: i32, deng_id: i32) -> DengPoints {
+        DengPoints { id, deng_id, rule: String::from("users_first_deng"), points: 1 }
+    }
+
+    #[test]
+    fn leaves_out_rows_stored_as_imported() {
+        let rows = unstored("deng point", &[points(1, 1), points(2, 2)], vec![points(1, 1)], |points| points.id).unwrap();
+
+        assert_eq!(rows, vec![points(2, 2)]);
+    }
+
+    #[test]
+    fn fails_on_a_different_row_with_the_same_id() {
+        assert!(unstored("deng point", &[points(1, 1), points(2, 2)], vec![points(2, 7)], |points| points.id).is_err());
+    }
+}
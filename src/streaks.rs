@@ -0,0 +1,210 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use config::Config;
+use runner::Rollovers;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use types::Deng;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Streak {
+    pub current: u32,
+    pub longest: u32,
+}
+
+// Days that don't break a streak if nobody dengs on them, such as weekends and public holidays
+pub struct SkipDays {
+    weekdays: Vec<Weekday>,
+    dates: Vec<NaiveDate>,
+}
+
+impl SkipDays {
+    pub fn from_config(config: &Config) -> Self {
+        let weekdays = config.streak_skip_weekdays.iter()
+            .filter_map(|day| match day.parse::<Weekday>() {
+                Ok(weekday) => Some(weekday),
+                Err(_) => {
+                    warn!("Invalid weekday '{}' in STREAK_SKIP_WEEKDAYS", day);
+                    None
+                }
+            })
+            .collect();
+
+        let dates = config.streak_skip_dates.iter()
+            .filter_map(|date| match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(date) => Some(date),
+                Err(e) => {
+                    warn!("Invalid date '{}' in STREAK_SKIP_DATES: {}", date, e);
+                    None
+                }
+            })
+            .collect();
+
+        SkipDays { weekdays, dates }
+    }
+
+    pub fn skips(&self, day: NaiveDate) -> bool {
+        self.weekdays.contains(&day.weekday()) || self.dates.contains(&day)
+    }
+
+    // True if nothing but skippable days lie strictly between the two days
+    fn bridges(&self, from: NaiveDate, to: NaiveDate) -> bool {
+        let mut day = from.succ();
+        while day < to {
+            if !self.skips(day) {
+                return false;
+            }
+            day = day.succ();
+        }
+        true
+    }
+}
+
+// Streaks over a set of days on which a user successfully denged.
+// Today doesn't break the current streak until it's over.
+pub fn calculate(days: &BTreeSet<NaiveDate>, today: NaiveDate, skip: &SkipDays) -> Streak {
+    let mut streak = Streak::default();
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+
+    for &day in days {
+        run = match previous {
+            Some(previous) if skip.bridges(previous, day) => run + 1,
+            _ => 1
+        };
+        streak.longest = streak.longest.max(run);
+        previous = Some(day);
+    }
+
+    if let Some(last) = previous {
+        if last == today || skip.bridges(last, today) {
+            streak.current = run;
+        }
+    }
+
+    streak
+}
+
+// Voided dengs don't count, and days are split at the recorded rollover times
+pub fn by_user(dengs: &[Deng], voided: &HashSet<i32>, rollovers: &Rollovers, today: NaiveDate, skip: &SkipDays) -> HashMap<String, Streak> {
+    dengs.iter()
+        .filter(|deng| deng.successful && !voided.contains(&deng.id))
+        .fold(HashMap::new(), |mut map: HashMap<&str, BTreeSet<NaiveDate>>, deng| {
+            map.entry(deng.user_id.as_str()).or_insert_with(BTreeSet::new).insert(rollovers.day_of(deng.ts));
+            map
+        })
+        .into_iter()
+        .map(|(user_id, days)| (String::from(user_id), calculate(&days, today, skip)))
+        .collect()
+}
+
+// The streak a user will be on once today's deng is counted. Voided dengs should already have been left out
+pub fn with_today(dengs: &[Deng], rollovers: &Rollovers, today: NaiveDate, skip: &SkipDays) -> Streak {
+    let mut days = dengs.iter()
+        .filter(|deng| deng.successful)
+        .map(|deng| rollovers.day_of(deng.ts))
+        .collect::<BTreeSet<_>>();
+    days.insert(today);
+
+    calculate(&days, today, skip)
+}
+
+pub fn format_streaks(streaks: &HashMap<String, Streak>) -> String {
+    let mut ordered = streaks.iter()
+        .filter(|&(_, streak)| streak.longest > 0)
+        .collect::<Vec<_>>();
+
+    ordered.sort_by(|first, second| {
+        (second.1.current, second.1.longest).cmp(&(first.1.current, first.1.longest))
+    });
+
+    match ordered.len() {
+        0 => String::from("Nobody has a streak yet!"),
+        _ => {
+            let lines = ordered.into_iter()
+                .map(|(user_id, streak)| {
+                    format!(":fire: *{}* <@{}> (best {})", streak.current, user_id, streak.longest)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("*Deng Streaks*\n{}", lines)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner::DayCycle;
+    use std::time::{Duration, SystemTime};
+
+    fn day(date: u32) -> NaiveDate {
+        // July 2018 starts on a Sunday, so the 2nd is a Monday
+        NaiveDate::from_ymd(2018, 7, date)
+    }
+
+    fn days(dates: &[u32]) -> BTreeSet<NaiveDate> {
+        dates.iter().map(|&date| day(date)).collect()
+    }
+
+    fn weekends() -> SkipDays {
+        SkipDays { weekdays: vec![Weekday::Sat, Weekday::Sun], dates: vec![] }
+    }
+
+    fn no_skips() -> SkipDays {
+        SkipDays { weekdays: vec![], dates: vec![] }
+    }
+
+    #[test]
+    fn weekends_bridge_a_streak() {
+        let streak = calculate(&days(&[5, 6, 9]), day(9), &weekends());
+
+        assert_eq!(streak, Streak { current: 3, longest: 3 });
+    }
+
+    #[test]
+    fn missing_a_weekday_breaks_a_streak() {
+        let streak = calculate(&days(&[2, 3, 5]), day(5), &weekends());
+
+        assert_eq!(streak, Streak { current: 1, longest: 2 });
+    }
+
+    #[test]
+    fn today_does_not_break_a_streak_until_it_is_over() {
+        assert_eq!(calculate(&days(&[2, 3]), day(4), &no_skips()), Streak { current: 2, longest: 2 });
+        assert_eq!(calculate(&days(&[2, 3]), day(5), &no_skips()), Streak { current: 0, longest: 2 });
+    }
+
+    #[test]
+    fn skip_dates_bridge_a_streak() {
+        let skip = SkipDays { weekdays: vec![], dates: vec![day(3)] };
+
+        assert_eq!(calculate(&days(&[2, 4]), day(4), &skip), Streak { current: 2, longest: 2 });
+        assert_eq!(calculate(&days(&[2, 4]), day(4), &no_skips()), Streak { current: 1, longest: 1 });
+    }
+
+    fn deng(id: i32, date: u32) -> Deng {
+        Deng {
+            id,
+            // Noon in Perth, well clear of the rollover
+            ts: SystemTime::from(DayCycle::for_day(day(date)).start()) + Duration::from_secs(3 * 60 * 60),
+            user_id: String::from("alice"),
+            successful: true,
+            days_first_deng: false,
+            users_first_deng: true,
+            points: 1,
+            thread_reply: false,
+            placement: Some(1),
+            channel_id: None,
+            slack_ts: None,
+        }
+    }
+
+    #[test]
+    fn voided_dengs_do_not_count() {
+        let dengs = vec![deng(1, 2), deng(2, 3), deng(3, 4)];
+        let voided = vec![2].into_iter().collect();
+
+        let streaks = by_user(&dengs, &voided, &Rollovers::default(), day(4), &no_skips());
+
+        assert_eq!(streaks["alice"], Streak { current: 1, longest: 1 });
+    }
+}
@@ -0,0 +1,150 @@
+// Everything after the slash command itself, e.g. "/deng grant @someone 2 helping out"
+#[derive(Debug, PartialEq)]
+pub enum Subcommand {
+    Scoreboard,
+    Help,
+    Streaks,
+    Void { deng_id: i32, reason: String },
+    Grant { user: String, points: i32, reason: String },
+    Deduct { user: String, points: i32, reason: String },
+    Undo,
+    Reload,
+    Audit { count: i64 },
+    Seasons,
+    Season { season_id: i32 },
+    EndSeason { name: Option<String> },
+    Rating,
+    RecomputeRatings,
+    Hot,
+    Teams,
+    Vs { first: String, second: String },
+    Me,
+    Chart { days: i64 },
+    Heatmap { user: Option<String> },
+}
+
+const DEFAULT_AUDIT_COUNT: i64 = 10;
+// Enough to be useful without flooding the channel
+const MAX_AUDIT_COUNT: i64 = 100;
+
+pub const USAGE: &str = "Usage:
+`/deng` - show the scoreboard
+`/deng help` - show this message
+`/deng streaks` - show everyone's current and longest daily streaks
+`/deng hot` - show who has been scoring the most lately
+`/deng teams` - show the team standings for this season
+`/deng vs @user @user` - compare two users head to head
+`/deng me` - show your own statistics
+`/deng chart [week|month|year]` - draw a chart of the top scores over time
+`/deng heatmap [@user]` - show when people deng, by weekday and hour
+`/deng seasons` - show past seasons and their champions
+`/deng season <id>` - show a past season's final standings
+`/deng rating` - show everyone's rating from the daily races to deng first
+Admins only:
+`/deng void <deng id> [reason]` - void a deng so it no longer scores
+`/deng grant @user <points> <reason>` - give a user points
+`/deng deduct @user <points> <reason>` - take points from a user
+`/deng undo` - undo your last adjustment
+`/deng reload` - reload the bot configuration
+`/deng audit [count]` - show the most recent changes to scores and configuration
+`/deng season end [name]` - end the current season and start a new one
+`/deng rating recompute` - recalculate every rating from scratch";
+
+impl Subcommand {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut words = text.split_whitespace();
+
+        let subcommand = match words.next() {
+            None | Some("scoreboard") => Subcommand::Scoreboard,
+            Some("help") => Subcommand::Help,
+            Some("streaks") => Subcommand::Streaks,
+            Some("hot") => Subcommand::Hot,
+            Some("teams") => Subcommand::Teams,
+            Some("me") => Subcommand::Me,
+            Some("heatmap") => Subcommand::Heatmap { user: words.next().map(String::from) },
+            Some("chart") => match words.next() {
+                Some("week") => Subcommand::Chart { days: 7 },
+                Some("month") | None => Subcommand::Chart { days: 30 },
+                Some("year") => Subcommand::Chart { days: 365 },
+                Some(other) => return Err(format!("Unknown chart period `{}`.\n{}", other, USAGE))
+            },
+            Some("vs") => match (words.next(), words.next()) {
+                (Some(first), Some(second)) => Subcommand::Vs { first: String::from(first), second: String::from(second) },
+                _ => return Err(format!("Two users are needed to compare.\n{}", USAGE))
+            },
+            Some("void") => {
+                let deng_id = parse_number(words.next(), "deng ID")?;
+                Subcommand::Void { deng_id, reason: rest(words) }
+            },
+            Some("grant") => {
+                let (user, points, reason) = parse_adjustment(words)?;
+                Subcommand::Grant { user, points, reason }
+            },
+            Some("deduct") => {
+                let (user, points, reason) = parse_adjustment(words)?;
+                Subcommand::Deduct { user, points, reason }
+            },
+            Some("undo") => Subcommand::Undo,
+            Some("reload") => Subcommand::Reload,
+            Some("audit") => match words.next() {
+                Some(count) => {
+                    let count: i64 = count.parse().map_err(|_| format!("`{}` is not a valid count.", count))?;
+                    Subcommand::Audit { count: count.max(1).min(MAX_AUDIT_COUNT) }
+                },
+                None => Subcommand::Audit { count: DEFAULT_AUDIT_COUNT }
+            },
+            Some("seasons") => Subcommand::Seasons,
+            Some("season") => match words.next() {
+                Some("end") => {
+                    let name = rest(words);
+                    Subcommand::EndSeason { name: if name.is_empty() { None } else { Some(name) } }
+                },
+                season_id => Subcommand::Season { season_id: parse_number(season_id, "season ID")? }
+            },
+            Some("rating") => match words.next() {
+                Some("recompute") => Subcommand::RecomputeRatings,
+                None => Subcommand::Rating,
+                Some(other) => return Err(format!("Unknown rating command `{}`.\n{}", other, USAGE))
+            },
+            Some(other) => return Err(format!("Unknown command `{}`.\n{}", other, USAGE))
+        };
+
+        Ok(subcommand)
+    }
+
+    pub fn requires_admin(&self) -> bool {
+        match *self {
+            Subcommand::Void { .. } | Subcommand::Grant { .. } | Subcommand::Deduct { .. } | Subcommand::Undo |
+            Subcommand::Reload | Subcommand::Audit { .. } | Subcommand::EndSeason { .. } |
+            Subcommand::RecomputeRatings => true,
+            _ => false
+        }
+    }
+}
+
+fn parse_adjustment<'a, I>(mut words: I) -> Result<(String, i32, String), String> where I: Iterator<Item=&'a str> {
+    let user = words.next()
+        .map(String::from)
+        .ok_or_else(|| format!("Missing user.\n{}", USAGE))?;
+
+    let points = parse_number(words.next(), "number of points")?;
+    if points <= 0 {
+        return Err(String::from("Points must be a positive number."));
+    }
+
+    let reason = rest(words);
+    if reason.is_empty() {
+        return Err(String::from("A reason is required for point adjustments."));
+    }
+
+    Ok((user, points, reason))
+}
+
+fn parse_number(word: Option<&str>, name: &str) -> Result<i32, String> {
+    let word = word.ok_or_else(|| format!("Missing {}.\n{}", name, USAGE))?;
+    word.parse().map_err(|_| format!("`{}` is not a valid {}.", word, name))
+}
+
+fn rest<'a, I>(words: I) -> String where I: Iterator<Item=&'a str> {
+    words.collect::<Vec<_>>().join(" ")
+}
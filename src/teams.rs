@@ -0,0 +1,138 @@
+use config::Config;
+use diesel::PgConnection;
+use scoreboard::{self, Penalties};
+use slack;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use storage::{self, Source};
+use types::{Adjustment, Deng, Error, TeamMember};
+
+#[derive(Debug)]
+pub struct TeamStanding {
+    pub team: String,
+    pub points: i32,
+    pub members: usize,
+}
+
+// Which team each user should be on right now, keyed by user ID.
+// Teams listed in TEAMS take priority over those mapped from Slack user groups
+pub fn configured(config: &Config) -> Result<HashMap<String, String>, Error> {
+    let mut teams = HashMap::new();
+
+    if !config.team_usergroups.is_empty() {
+        for (team, members) in load_usergroups(&config.team_usergroups)? {
+            for user_id in members {
+                teams.insert(user_id, team.clone());
+            }
+        }
+    }
+
+    // Teams are listed in TEAMS as `name:user_id user_id ...`
+    for spec in &config.teams {
+        let mut parts = spec.splitn(2, ':');
+        match (parts.next().map(str::trim), parts.next()) {
+            (Some(team), Some(members)) if !team.is_empty() => {
+                for user_id in members.split_whitespace() {
+                    teams.insert(String::from(user_id), String::from(team));
+                }
+            },
+            _ => warn!("Invalid team '{}', expected name:user_id user_id ...", spec)
+        }
+    }
+
+    Ok(teams)
+}
+
+// Members of the Slack user groups with the given handles, keyed by the group's name
+fn load_usergroups(handles: &[String]) -> Result<Vec<(String, Vec<String>)>, Error> {
+    let client = slack::api::requests::default_client()
+        .map_err(|e| Error::from(format!("Could not create Slack web client: {}", e)))?;
+
+    let request = slack::api::usergroups::ListRequest {
+        include_users: Some(true),
+        ..Default::default()
+    };
+
+    let response = slack::api::usergroups::list(&client, dotenv!("SLACK_API_KEY"), &request)
+        .map_err(|e| Error::from(format!("Could not load Slack user groups: {}", e)))?;
+
+    let groups = response.usergroups.unwrap_or_default()
+        .into_iter()
+        .filter(|group| group.handle.as_ref().map_or(false, |handle| handles.contains(handle)))
+        .map(|group| {
+            let name = group.name.or(group.handle).unwrap_or_default();
+            (name, group.users.unwrap_or_default())
+        })
+        .collect::<Vec<_>>();
+
+    if groups.len() < handles.len() {
+        warn!("Only found {} of the {} user groups in TEAM_USERGROUPS", groups.len(), handles.len());
+    }
+
+    Ok(groups)
+}
+
+// Brings team_members in line with the config, closing the memberships of anyone who has moved
+pub fn sync(conn: &PgConnection, config: &Config, source: Source) -> Result<(), Error> {
+    let wanted = configured(config)?;
+
+    let changed = storage::sync_team_members(conn, &wanted, source)?;
+    if changed > 0 {
+        info!("Updated {} team memberships", changed);
+    }
+
+    Ok(())
+}
+
+fn team_at<'a>(members: &'a [TeamMember], user_id: &str, ts: SystemTime) -> Option<&'a str> {
+    members.iter()
+        .find(|member| member.user_id == user_id && member.joined <= ts && member.left.map_or(true, |left| ts < left))
+        .map(|member| member.team.as_str())
+}
+
+// Dengs and adjustments count towards whichever team the user was on at the time,
+// so moving teams doesn't take your points with you
+pub fn tally(dengs: &[Deng], adjustments: &[Adjustment], penalties: &Penalties, members: &[TeamMember]) -> Vec<TeamStanding> {
+    let mut teams = members.iter()
+        .map(|member| member.team.as_str())
+        .collect::<Vec<_>>();
+    teams.sort();
+    teams.dedup();
+
+    let mut standings = teams.into_iter()
+        .map(|team| {
+            let team_dengs = dengs.iter()
+                .filter(|deng| team_at(members, &deng.user_id, deng.ts) == Some(team));
+            // Voids are always included so voided dengs never score, whichever team they were for
+            let team_adjustments = adjustments.iter()
+                .filter(|adjustment| {
+                    adjustment.deng_id.is_some() || team_at(members, &adjustment.user_id, adjustment.ts) == Some(team)
+                });
+
+            TeamStanding {
+                team: String::from(team),
+                points: scoreboard::tally(team_dengs, team_adjustments, penalties).iter().map(|standing| standing.points).sum(),
+                members: members.iter().filter(|member| member.team == team && member.left.is_none()).count(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    standings.sort_by(|first, second| second.points.cmp(&first.points));
+    standings
+}
+
+pub fn format_standings(title: &str, standings: &[TeamStanding]) -> String {
+    match standings.len() {
+        0 => String::from("There are no teams yet."),
+        _ => {
+            let lines = standings.iter()
+                .enumerate()
+                .map(|(rank, standing)| {
+                    format!("{}. *{}* - {} ({} members)", rank + 1, standing.team, standing.points, standing.members)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("*{}*\n{}", title, lines)
+        }
+    }
+}
@@ -1,35 +1,174 @@
 use ::std;
 use ::std::error::Error as StdError;
+use diesel::sql_types::{Double, Varchar};
+use storage::{adjustments, deng_points, dengs};
 
-pub type DbConnection = ::r2d2::PooledConnection<::r2d2_diesel::ConnectionManager<::diesel::PgConnection>>;
+pub type DbPool = ::r2d2::Pool<::r2d2_diesel::ConnectionManager<::diesel::PgConnection>>;
 
+// Slack identifies a message by its channel and timestamp, so redelivered messages can be spotted
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    pub channel_id: String,
+    pub ts: String,
+}
+
+// Slack timestamps are seconds since the epoch, with microseconds after the dot
+pub fn parse_slack_ts(ts: &str) -> Option<std::time::SystemTime> {
+    let mut parts = ts.splitn(2, '.');
+    let seconds = parts.next().and_then(|seconds| seconds.parse::<u64>().ok())?;
+    let micros = parts.next().map_or(Some(0), |micros| micros.parse::<u32>().ok())?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::new(seconds, micros * 1000))
+}
+
+// Each message carries when Slack says it was posted, so missed messages caught up on later keep their own time
 pub enum Broadcast {
-    Deng(String),
-    NonDeng(String)
+    Deng { user_id: String, ts: std::time::SystemTime, message_id: Option<MessageId> },
+    NonDeng { user_id: String, ts: std::time::SystemTime, thread_reply: bool, message_id: Option<MessageId> },
+    // Attempts from earlier days that were posted while disconnected, oldest first
+    Missed { attempts: Vec<::replay::Attempt> }
 }
 
-#[derive(Debug, Queryable)]
+#[derive(Clone, Debug, PartialEq, Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "dengs"]
 pub struct Deng {
     pub id: i32,
+    #[serde(serialize_with = "serialize_ts", deserialize_with = "deserialize_ts")]
     pub ts: std::time::SystemTime,
     pub user_id: String,
     pub successful: bool,
     pub days_first_deng: bool,
     pub users_first_deng: bool,
+    pub points: i32,
+    pub thread_reply: bool,
+    // Order in which the user denged that day, only set on their first successful deng
+    pub placement: Option<i32>,
+    pub channel_id: Option<String>,
+    pub slack_ts: Option<String>,
 }
 
 impl Deng {
+    // Points are fixed by the scoring rules in place when the deng was stored
     pub fn value(&self) -> i32 {
-        let mut value = 0;
-        if self.successful {
-            if self.users_first_deng {
-                value += 1
-            }
-            if self.days_first_deng {
-                value += 1
-            }
-        }
-        value
+        if self.successful { self.points } else { 0 }
+    }
+}
+
+// One line of a stored deng's point breakdown
+#[derive(Clone, Debug, PartialEq, Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "deng_points"]
+pub struct DengPoints {
+    pub id: i32,
+    pub deng_id: i32,
+    pub rule: String,
+    pub points: i32,
+}
+
+// A user's score with each deng's value decayed by its age
+#[derive(Debug, QueryableByName)]
+pub struct HotScore {
+    #[sql_type = "Varchar"]
+    pub user_id: String,
+    #[sql_type = "Double"]
+    pub score: f64,
+}
+
+// Manual score corrections made by admins. An adjustment with a deng ID voids that deng entirely,
+// otherwise its points are added to (or taken from) the user's score
+#[derive(Clone, Debug, PartialEq, Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "adjustments"]
+pub struct Adjustment {
+    pub id: i32,
+    #[serde(serialize_with = "serialize_ts", deserialize_with = "deserialize_ts")]
+    pub ts: std::time::SystemTime,
+    pub admin_id: String,
+    pub user_id: String,
+    pub deng_id: Option<i32>,
+    pub points: i32,
+    pub reason: String,
+    pub undone: bool,
+}
+
+#[derive(Debug, Queryable)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub ts: std::time::SystemTime,
+    pub actor: String,
+    pub action: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub source: String,
+}
+
+// A season's standings are archived when it ends, and a new season started in its place
+#[derive(Debug, Queryable, Serialize)]
+pub struct Season {
+    pub id: i32,
+    pub name: String,
+    #[serde(serialize_with = "serialize_ts")]
+    pub started: std::time::SystemTime,
+    #[serde(serialize_with = "serialize_optional_ts")]
+    pub ended: Option<std::time::SystemTime>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct SeasonStanding {
+    pub id: i32,
+    pub season_id: i32,
+    pub user_id: String,
+    pub points: i32,
+    pub rank: i32,
+}
+
+#[derive(Debug, Queryable, Serialize)]
+pub struct UnlockedAchievement {
+    pub id: i32,
+    pub user_id: String,
+    pub achievement: String,
+    pub deng_id: i32,
+    #[serde(serialize_with = "serialize_ts")]
+    pub unlocked: std::time::SystemTime,
+}
+
+// A user's rating after one day's race
+#[derive(Debug, Queryable)]
+pub struct Rating {
+    pub id: i32,
+    pub user_id: String,
+    pub day: ::chrono::NaiveDate,
+    pub rating: f64,
+    pub change: f64,
+    pub placement: i32,
+    pub racers: i32,
+}
+
+// A user's time on a team. Memberships are closed rather than deleted when someone moves,
+// so their old points stay with their old team
+#[derive(Debug, Queryable, Serialize)]
+pub struct TeamMember {
+    pub id: i32,
+    pub team: String,
+    pub user_id: String,
+    #[serde(serialize_with = "serialize_ts")]
+    pub joined: std::time::SystemTime,
+    #[serde(serialize_with = "serialize_optional_ts")]
+    pub left: Option<std::time::SystemTime>,
+}
+
+fn serialize_ts<S>(ts: &std::time::SystemTime, serializer: S) -> Result<S::Ok, S::Error> where S: ::serde::Serializer {
+    serializer.serialize_str(&::chrono::DateTime::<::chrono::Utc>::from(*ts).to_rfc3339())
+}
+
+fn deserialize_ts<'de, D>(deserializer: D) -> Result<std::time::SystemTime, D::Error> where D: ::serde::Deserializer<'de> {
+    let ts = <String as ::serde::Deserialize>::deserialize(deserializer)?;
+    ::chrono::DateTime::parse_from_rfc3339(&ts)
+        .map(std::time::SystemTime::from)
+        .map_err(::serde::de::Error::custom)
+}
+
+fn serialize_optional_ts<S>(ts: &Option<std::time::SystemTime>, serializer: S) -> Result<S::Ok, S::Error> where S: ::serde::Serializer {
+    match *ts {
+        Some(ref ts) => serialize_ts(ts, serializer),
+        None => serializer.serialize_none()
     }
 }
 
@@ -82,6 +221,27 @@ impl SlackInfo {
     }
 }
 
+impl SlackInfo {
+    // Slack escapes mentions in commands as <@U1234|name>, but fall back to looking up a plain @name
+    pub fn find_user_id(&self, mention: &str) -> Option<String> {
+        if mention.starts_with("<@") && mention.ends_with('>') {
+            let escaped = &mention[2..mention.len() - 1];
+            return escaped.split('|').next().map(String::from);
+        }
+
+        let name = mention.trim_left_matches('@');
+        self.users.iter()
+            .find(|user| {
+                let matches_name = user.name.as_ref().map_or(false, |user_name| user_name == name);
+                let matches_display_name = user.profile.as_ref()
+                    .and_then(|profile| profile.display_name.as_ref())
+                    .map_or(false, |display_name| display_name == name);
+                matches_name || matches_display_name
+            })
+            .and_then(|user| user.id.clone())
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
     description: String,
@@ -150,4 +310,31 @@ impl From<::diesel::result::Error> for Error {
             cause: Some(Box::new(error))
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<::reqwest::Error> for Error {
+    fn from(error: ::reqwest::Error) -> Self {
+        Error {
+            description: String::from(error.description()),
+            cause: Some(Box::new(error))
+        }
+    }
+}
+
+impl From<::r2d2::Error> for Error {
+    fn from(error: ::r2d2::Error) -> Self {
+        Error {
+            description: String::from(error.description()),
+            cause: Some(Box::new(error))
+        }
+    }
+}
+
+impl From<::std::io::Error> for Error {
+    fn from(error: ::std::io::Error) -> Self {
+        Error {
+            description: String::from(error.description()),
+            cause: Some(Box::new(error))
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use runner::DayCycle;
+use scoreboard::{self, Penalties, Period, Standing, UserDisplay};
+use types::{Adjustment, Deng, DengPoints, SlackInfo};
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 40em; margin: 2em auto; color: #222; }
+nav a { margin-right: 1em; }
+nav a.active { font-weight: bold; text-decoration: none; color: #222; }
+table { border-collapse: collapse; width: 100%; }
+td, th { padding: 0.4em; text-align: left; border-bottom: 1px solid #ddd; }
+.swatch { display: inline-block; width: 0.8em; height: 0.8em; margin-right: 0.5em; }";
+
+pub fn leaderboard_page(info: &SlackInfo, period: Period, standings: &[Standing]) -> String {
+    let tabs = Period::all().iter()
+        .map(|tab| {
+            let class = if *tab == period { " class=\"active\"" } else { "" };
+            format!("<a href=\"/leaderboard?period={}\"{}>{}</a>", tab.param(), class, tab.title())
+        })
+        .collect::<Vec<_>>()
+        .join("");
+
+    // Only show the penalty column if anyone has actually been penalised
+    let show_penalties = standings.iter().any(|standing| standing.penalty > 0);
+
+    let rows = match standings.len() {
+        0 => String::from("<tr><td colspan=\"3\">No scores yet!</td></tr>"),
+        _ => standings.iter()
+            .enumerate()
+            .map(|(rank, standing)| {
+                let penalty = if show_penalties { format!("<td>-{}</td>", standing.penalty) } else { String::new() };
+                format!("<tr><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
+                        rank + 1, user_link(info, &standing.user_id), standing.points, penalty)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let penalty_header = if show_penalties { "<th>Penalties</th>" } else { "" };
+
+    let body = format!("<h1>Deng Champions</h1>\n<nav>{}</nav>\n\
+                        <table>\n<tr><th>#</th><th>Denger</th><th>Points</th>{}</tr>\n{}\n</table>",
+                       tabs, penalty_header, rows);
+
+    layout(&format!("Deng Champions - {}", period.title()), &body)
+}
+
+pub fn user_page(info: &SlackInfo,
+                 user_id: &str,
+                 dengs: &[Deng],
+                 adjustments: &[Adjustment],
+                 breakdowns: &[DengPoints],
+                 penalties: &Penalties) -> String {
+    let name = display_name(info, user_id);
+
+    let totals = Period::all().iter()
+        .map(|period| {
+            let since = period.since();
+            let standings = scoreboard::tally(
+                dengs.iter().filter(|deng| since.map_or(true, |since| deng.ts >= since)),
+                adjustments.iter().filter(|adjustment| since.map_or(true, |since| adjustment.ts >= since)),
+                penalties);
+            let points = standings.first().map_or(0, |standing| standing.points);
+            format!("<tr><td>{}</td><td>{}</td></tr>", period.title(), points)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let successful = dengs.iter().filter(|deng| deng.successful).count();
+    let first_of_day = dengs.iter().filter(|deng| deng.successful && deng.days_first_deng).count();
+
+    // Dengs are loaded newest first
+    let history = dengs.iter()
+        .filter(|deng| deng.successful)
+        .take(30)
+        .map(|deng| {
+            let voided = adjustments.iter().any(|adjustment| adjustment.deng_id == Some(deng.id));
+            let breakdown = breakdowns.iter()
+                .filter(|item| item.deng_id == deng.id)
+                .map(|item| format!("{} {:+}", escape(&item.rule), item.points))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    DayCycle::local(deng.ts).format("%a %e %b %Y %H:%M:%S"),
+                    if voided { String::from("voided") } else { deng.value().to_string() },
+                    breakdown)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let corrections = adjustments.iter()
+        .filter(|adjustment| adjustment.deng_id.is_none())
+        .map(|adjustment| {
+            format!("<tr><td>{}</td><td>{:+}</td><td>{}</td></tr>",
+                    DayCycle::local(adjustment.ts).format("%a %e %b %Y"), adjustment.points, escape(&adjustment.reason))
+        })
+        .collect::<Vec<_>>();
+
+    let corrections = match corrections.len() {
+        0 => String::new(),
+        _ => format!("<h2>Adjustments</h2>\n<table>\n<tr><th>When</th><th>Points</th><th>Reason</th></tr>\n{}\n</table>",
+                     corrections.join("\n"))
+    };
+
+    let body = format!("<p><a href=\"/leaderboard\">&larr; Leaderboard</a></p>\n<h1>{}</h1>\n\
+                        <p>{} successful dengs, {} first of the day, {} failed attempts.</p>\n\
+                        <h2>Points</h2>\n<table>\n{}\n</table>\n\
+                        <h2>Recent dengs</h2>\n<table>\n<tr><th>When</th><th>Points</th><th>Breakdown</th></tr>\n{}\n</table>\n{}",
+                       name, successful, first_of_day, dengs.len() - successful, totals, history, corrections);
+
+    layout(&format!("{} - Deng Champions", name), &body)
+}
+
+pub fn not_found_page() -> String {
+    layout("Not found", "<h1>Not found</h1>\n<p><a href=\"/leaderboard\">Back to the leaderboard</a></p>")
+}
+
+// Both the title and body are expected to be escaped already
+fn layout(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>\n{}\n</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+            title, STYLE, body)
+}
+
+fn user_link(info: &SlackInfo, user_id: &str) -> String {
+    let color = UserDisplay::find(&info.users, user_id)
+        .map(|user| user.color)
+        .unwrap_or_else(|_| String::from("#000000"));
+
+    format!("<span class=\"swatch\" style=\"background: {}\"></span><a href=\"/leaderboard/user/{}\">{}</a>",
+            escape(&color), escape(user_id), display_name(info, user_id))
+}
+
+// Escaped and ready for insertion into a page
+fn display_name(info: &SlackInfo, user_id: &str) -> String {
+    match UserDisplay::find(&info.users, user_id) {
+        Ok(ref user) if user.username.is_empty() => escape(&user.full_name),
+        Ok(user) => format!("{} ({})", escape(&user.username), escape(&user.full_name)),
+        Err(e) => {
+            debug!("Could not find display name for {}: {}", user_id, e);
+            escape(user_id)
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                _ => escaped.push(c)
+            }
+            escaped
+        })
+}
+
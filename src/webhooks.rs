@@ -0,0 +1,353 @@
+extern crate hmac;
+extern crate sha2;
+extern crate hex;
+
+use self::hmac::{Hmac, Mac};
+use self::sha2::Sha256;
+use config::SharedConfig;
+use scoreboard::{self, Penalties};
+use reqwest;
+use runner::DayCycle;
+use serde_json;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use storage;
+use types::{DbPool, Deng, Error};
+
+const SIGNATURE_HEADER: &str = "X-Dengbot-Signature";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const INITIAL_BACKOFF_SECS: u64 = 1;
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    deng: DengPayload<'a>,
+    totals: TotalsPayload,
+}
+
+#[derive(Serialize)]
+struct DengPayload<'a> {
+    id: i32,
+    user_id: &'a str,
+    points: i32,
+    days_first_deng: bool,
+    users_first_deng: bool,
+    placement: Option<i32>,
+    timestamp: String,
+}
+
+#[derive(Serialize)]
+struct TotalsPayload {
+    points: i32,
+    rank: usize,
+}
+
+// Each URL gets its own delivery thread so a slow or dead endpoint only holds up its own queue
+pub struct Webhooks {
+    payloads: Option<Sender<Deng>>,
+}
+
+impl Webhooks {
+    pub fn new(shared_config: SharedConfig, db_pool: DbPool) -> Self {
+        let config = shared_config.get();
+        let workers = config.webhook_urls.iter()
+            .filter_map(|url| {
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+                    .build();
+
+                let worker = match client {
+                    Ok(client) => Worker {
+                        url: url.clone(),
+                        secret: config.webhook_secret.clone(),
+                        max_attempts: config.webhook_max_attempts,
+                        initial_backoff: Duration::from_secs(INITIAL_BACKOFF_SECS),
+                        client,
+                        dead_letters: Box::new(db_pool.clone()),
+                    },
+                    Err(e) => {
+                        error!("Could not build HTTP client for webhook {}: {}", url, e);
+                        return None;
+                    }
+                };
+
+                info!("Starting webhook delivery to {}", url);
+
+                let (tx, rx) = mpsc::channel();
+                thread::spawn(move || {
+                    for body in rx.iter() {
+                        worker.deliver(body);
+                    }
+                });
+
+                Some(tx)
+            })
+            .collect::<Vec<Sender<String>>>();
+
+        if workers.is_empty() {
+            return Webhooks { payloads: None };
+        }
+
+        // Totals come from tallying the season, so payloads are built on their own thread rather than the runner's
+        let (tx, rx) = mpsc::channel::<Deng>();
+        thread::spawn(move || {
+            for deng in rx.iter() {
+                match build_payload(&db_pool, &shared_config, &deng) {
+                    Ok(body) => {
+                        for worker in &workers {
+                            if let Err(e) = worker.send(body.clone()) {
+                                error!("Could not queue webhook delivery: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => error!("Could not build webhook payload: {}", e)
+                }
+            }
+        });
+
+        Webhooks { payloads: Some(tx) }
+    }
+
+    pub fn notify(&self, deng: &Deng) {
+        if let Some(ref payloads) = self.payloads {
+            if let Err(e) = payloads.send(deng.clone()) {
+                error!("Could not queue webhook payload: {}", e);
+            }
+        }
+    }
+}
+
+// Totals are the user's standing on the current season's scoreboard, the same as `/deng` shows
+fn build_payload(db_pool: &DbPool, config: &SharedConfig, deng: &Deng) -> Result<String, Error> {
+    let conn = db_pool.get()?;
+    let since = storage::current_season(&conn)?.map(|season| season.started);
+    let standings = scoreboard::tally(&storage::load_since(&conn, since)?,
+                                      &storage::load_adjustments(&conn, since)?,
+                                      &Penalties::from_config(&config.get()));
+
+    let points = standings.iter()
+        .find(|standing| standing.user_id == deng.user_id)
+        .map_or(0, |standing| standing.points);
+    let rank = 1 + standings.iter().filter(|standing| standing.points > points).count();
+
+    let payload = WebhookPayload {
+        event: "deng",
+        deng: DengPayload {
+            id: deng.id,
+            user_id: &deng.user_id,
+            points: deng.value(),
+            days_first_deng: deng.days_first_deng,
+            users_first_deng: deng.users_first_deng,
+            placement: deng.placement,
+            timestamp: DayCycle::local(deng.ts).to_rfc3339(),
+        },
+        totals: TotalsPayload { points, rank },
+    };
+
+    serde_json::to_string(&payload).map_err(Error::from)
+}
+
+// Where deliveries go once they've run out of attempts
+trait DeadLetters: Send {
+    fn store(&self, url: &str, body: &str, attempts: u32, error: &Error) -> Result<(), Error>;
+}
+
+impl DeadLetters for DbPool {
+    fn store(&self, url: &str, body: &str, attempts: u32, error: &Error) -> Result<(), Error> {
+        let conn = self.get()?;
+        storage::store_dead_letter(&conn, url, body, attempts as i32, &format!("{}", error))?;
+        Ok(())
+    }
+}
+
+struct Worker {
+    url: String,
+    secret: Option<String>,
+    max_attempts: u32,
+    // Doubled after each failed attempt
+    initial_backoff: Duration,
+    client: reqwest::Client,
+    dead_letters: Box<DeadLetters>,
+}
+
+impl Worker {
+    fn deliver(&self, body: String) {
+        let mut backoff = self.initial_backoff;
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let error = match self.post(&body) {
+                Ok(()) => {
+                    debug!("Delivered webhook to {} after {} attempt(s)", self.url, attempts);
+                    return;
+                },
+                Err(e) => e
+            };
+
+            warn!("Webhook delivery to {} failed on attempt {}/{}: {}", self.url, attempts, self.max_attempts, error);
+
+            if attempts >= self.max_attempts {
+                self.dead_letter(&body, attempts, &error);
+                return;
+            }
+
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+
+    fn post(&self, body: &str) -> Result<(), Error> {
+        let mut headers = reqwest::header::Headers::new();
+        headers.set(reqwest::header::ContentType::json());
+        if let Some(ref secret) = self.secret {
+            headers.set_raw(SIGNATURE_HEADER, format!("sha256={}", sign(secret, body)?));
+        }
+
+        let response = self.client.post(self.url.as_str())
+            .headers(headers)
+            .body(String::from(body))
+            .send()
+            .map_err(|e| Error::from(format!("Request failed: {}", e)))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::from(format!("Endpoint responded with {}", response.status())))
+        }
+    }
+
+    fn dead_letter(&self, body: &str, attempts: u32, error: &Error) {
+        error!("Giving up on webhook delivery to {} after {} attempts", self.url, attempts);
+
+        if let Err(e) = self.dead_letters.store(&self.url, body, attempts, error) {
+            error!("Could not store dead-lettered webhook: {}", e);
+        }
+    }
+}
+
+// Hex-encoded HMAC-SHA256 of the body, so receivers can check the payload came from us
+fn sign(secret: &str, body: &str) -> Result<String, Error> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes())
+        .map_err(|_| Error::from("Invalid webhook secret"))?;
+    mac.input(body.as_bytes());
+    Ok(hex::encode(mac.result().code().as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::Receiver;
+
+    struct Request {
+        headers: Vec<String>,
+        body: String,
+    }
+
+    // A stand-in endpoint that answers each request with the next status in turn, passing on what it was sent
+    fn serve(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_lowercase());
+                }
+
+                let length = headers.iter()
+                    .filter_map(|header| if header.starts_with("content-length:") { header[15..].trim().parse().ok() } else { None })
+                    .next()
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                write!(stream, "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+                tx.send(Request { headers, body: String::from_utf8(body).unwrap() }).unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordedDeadLetters {
+        stored: Arc<Mutex<Vec<(String, String, u32)>>>,
+    }
+
+    impl DeadLetters for RecordedDeadLetters {
+        fn store(&self, url: &str, body: &str, attempts: u32, _error: &Error) -> Result<(), Error> {
+            self.stored.lock().unwrap().push((String::from(url), String::from(body), attempts));
+            Ok(())
+        }
+    }
+
+    fn worker(url: String, max_attempts: u32, dead_letters: RecordedDeadLetters) -> Worker {
+        Worker {
+            url,
+            secret: Some(String::from("key")),
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            client: reqwest::Client::new(),
+            dead_letters: Box::new(dead_letters),
+        }
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        assert_eq!(sign("key", "The quick brown fox jumps over the lazy dog").unwrap(),
+                   "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn sends_the_signed_body() {
+        let (url, requests) = serve(vec![200]);
+        let dead_letters = RecordedDeadLetters::default();
+        let body = r#"{"event":"deng"}"#;
+
+        worker(url, 3, dead_letters.clone()).deliver(String::from(body));
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request.body, body);
+        assert!(request.headers.contains(&format!("x-dengbot-signature: sha256={}", sign("key", body).unwrap())));
+        assert!(request.headers.contains(&String::from("content-type: application/json")));
+        assert!(dead_letters.stored.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retries_until_delivered() {
+        let (url, requests) = serve(vec![500, 503, 200]);
+        let dead_letters = RecordedDeadLetters::default();
+
+        worker(url, 5, dead_letters.clone()).deliver(String::from("{}"));
+
+        assert_eq!(requests.iter().take(3).count(), 3);
+        assert!(dead_letters.stored.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dead_letters_after_the_last_attempt() {
+        let (url, requests) = serve(vec![500, 500, 500]);
+        let dead_letters = RecordedDeadLetters::default();
+
+        worker(url.clone(), 3, dead_letters.clone()).deliver(String::from("{}"));
+
+        assert_eq!(requests.iter().take(3).count(), 3);
+        assert_eq!(*dead_letters.stored.lock().unwrap(), vec![(url, String::from("{}"), 3)]);
+    }
+}